@@ -0,0 +1,93 @@
+use crate::vec3::*;
+use crate::ray::*;
+
+// Axis aligned bounding box used by the BVH.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    // Inverted box that any union will overwrite.
+    pub fn empty() -> Aabb {
+        Aabb::new(Vec3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+                  Vec3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY))
+    }
+
+    pub fn from_points(points: &[Vec3]) -> Aabb {
+        points.iter().fold(Aabb::empty(), |b, &p| b.grow(p))
+    }
+
+    pub fn grow(self, p: Vec3) -> Aabb {
+        Aabb::new(Vec3::new(self.min.x.min(p.x), self.min.y.min(p.y), self.min.z.min(p.z)),
+                  Vec3::new(self.max.x.max(p.x), self.max.y.max(p.y), self.max.z.max(p.z)))
+    }
+
+    pub fn union(self, other: Aabb) -> Aabb {
+        self.grow(other.min).grow(other.max)
+    }
+
+    // Flat shapes (axis aligned triangles) get a sliver of thickness so the slab test still hits them.
+    pub fn padded(self) -> Aabb {
+        let delta = 0.0001;
+        let pad = |lo: f32, hi: f32| if hi - lo < delta { (lo - delta, hi + delta) } else { (lo, hi) };
+        let (x0, x1) = pad(self.min.x, self.max.x);
+        let (y0, y1) = pad(self.min.y, self.max.y);
+        let (z0, z1) = pad(self.min.z, self.max.z);
+        Aabb::new(Vec3::new(x0, y0, z0), Vec3::new(x1, y1, z1))
+    }
+
+    pub fn centroid(self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn extent(self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn surface_area(self) -> f32 {
+        let d = self.extent();
+        if d.x < 0. || d.y < 0. || d.z < 0. {
+            return 0.;
+        }
+        2. * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    // Index of the longest axis: 0 = x, 1 = y, 2 = z.
+    pub fn largest_axis(self) -> usize {
+        let d = self.extent();
+        if d.x > d.y && d.x > d.z {
+            0
+        } else if d.y > d.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    // Slab test. `inv_dir` is 1 / ray direction, computed once per traversal.
+    pub fn hit(&self, ray: &Ray, inv_dir: Vec3, t_min: f32, t_max: f32) -> bool {
+        let orig = ray.origin();
+        let mut t0 = t_min;
+        let mut t1 = t_max;
+        for axis in 0..3 {
+            let (o, inv) = (orig[axis], inv_dir[axis]);
+            let mut near = (self.min[axis] - o) * inv;
+            let mut far = (self.max[axis] - o) * inv;
+            if inv < 0. {
+                std::mem::swap(&mut near, &mut far);
+            }
+            t0 = if near > t0 { near } else { t0 };
+            t1 = if far < t1 { far } else { t1 };
+            if t1 < t0 {
+                return false;
+            }
+        }
+        true
+    }
+}
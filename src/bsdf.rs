@@ -0,0 +1,424 @@
+use std::f32::consts::PI;
+
+use crate::vec3::*;
+use crate::util::*;
+use crate::material::*;
+use crate::microfacet::*;
+use crate::sampling::*;
+
+/*
+Scattering functions, all in the local shading frame: the normal is +Z and `wo`, pointing back
+toward the viewer, is above the surface. `wi` is where the light comes from, below the
+surface means it was transmitted.
+
+Perfect mirrors and smooth glass are delta lobes. eval and pdf can't see them (they're 0 there),
+only sample picks them and marks the result specular.
+*/
+
+pub struct BsdfSample {
+    pub wi: Vec3,
+    // f * |cos| / pdf
+    pub weight: Vec3,
+    // Solid angle pdf of picking `wi`, 0 for delta lobes.
+    pub pdf: f32,
+    pub specular: bool
+}
+
+pub trait Bsdf {
+    // Fraction of light from `wi` scattered toward `wo`, per steradian.
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3;
+
+    // Picks `wi` with the three uniform numbers: `uc` chooses a lobe, a and b the direction.
+    fn sample(&self, wo: Vec3, uc: f32, a: f32, b: f32) -> Option<BsdfSample>;
+
+    // Solid angle density of `sample` returning `wi`, delta lobes left out.
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32;
+
+    // True when every lobe is a delta, so sampling lights is pointless.
+    fn is_specular(&self) -> bool {
+        false
+    }
+}
+
+fn mirror(w: Vec3) -> Vec3 {
+    Vec3::new(-w.x, -w.y, w.z)
+}
+
+fn flip(w: Vec3) -> Vec3 {
+    Vec3::new(w.x, w.y, -w.z)
+}
+
+// f * |cos| / pdf for a direction some lobe picked, with the density of the whole mixture.
+fn sampled<B: Bsdf>(bsdf: &B, wo: Vec3, wi: Vec3) -> Option<BsdfSample> {
+    let pdf = bsdf.pdf(wo, wi);
+    if pdf <= 0. {
+        return None;
+    }
+    Some(BsdfSample { wi, weight: bsdf.eval(wo, wi) * (wi.z.abs() / pdf), pdf, specular: false })
+}
+
+// Smooth glass: reflects or refracts with the Fresnel odds.
+pub struct DielectricBsdf {
+    //index on the outside over the index on the inside, as seen from wo
+    eta: f32
+}
+
+impl DielectricBsdf {
+    pub fn new(ior: f32, front_face: bool) -> DielectricBsdf {
+        DielectricBsdf { eta: if front_face { 1. / ior } else { ior } }
+    }
+}
+
+impl Bsdf for DielectricBsdf {
+    fn eval(&self, _wo: Vec3, _wi: Vec3) -> Vec3 {
+        Vec3::new(0., 0., 0.)
+    }
+
+    fn sample(&self, wo: Vec3, uc: f32, _a: f32, _b: f32) -> Option<BsdfSample> {
+        let fresnel = fresnel_dielectric(clamp(wo.z, 0., 1.), self.eta);
+        //the throughput stays put either way since each branch is picked with its own weight
+        let wi = if uc < fresnel { None } else { refract(wo, Vec3::new(0., 0., 1.), self.eta) };
+        Some(BsdfSample { wi: wi.unwrap_or_else(|| mirror(wo)), weight: Vec3::new(1., 1., 1.), pdf: 0., specular: true })
+    }
+
+    fn pdf(&self, _wo: Vec3, _wi: Vec3) -> f32 {
+        0.
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+}
+
+// GGX metal, a tinted mirror when it's smooth enough.
+pub struct ConductorBsdf {
+    eta: Vec3,
+    k: Vec3,
+    alpha: f32,
+    tint: Vec3
+}
+
+impl ConductorBsdf {
+    pub fn new(eta: Vec3, k: Vec3, roughness: f32, tint: Vec3) -> ConductorBsdf {
+        ConductorBsdf { eta, k, alpha: roughness_to_alpha(roughness), tint }
+    }
+}
+
+impl Bsdf for ConductorBsdf {
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        if is_smooth(self.alpha) || wo.z <= 0. || wi.z <= 0. {
+            return Vec3::new(0., 0., 0.);
+        }
+        let h = Vec3::unit_vec(wo + wi);
+        let f = fresnel_conductor(Vec3::dot(wo, h).max(0.), self.eta, self.k);
+        self.tint * f * (ggx_d(h, self.alpha) * smith_g2(wo, wi, self.alpha) / (4. * wo.z * wi.z))
+    }
+
+    fn sample(&self, wo: Vec3, _uc: f32, a: f32, b: f32) -> Option<BsdfSample> {
+        if wo.z <= 0. {
+            return None;
+        }
+        if is_smooth(self.alpha) {
+            let f = fresnel_conductor(clamp(wo.z, 0., 1.), self.eta, self.k);
+            return Some(BsdfSample { wi: mirror(wo), weight: self.tint * f, pdf: 0., specular: true });
+        }
+        //visible normal sampling: f * cos / pdf collapses to F * G1(wi)
+        let h = sample_vndf(wo, self.alpha, a, b);
+        let wi = reflect(wo, h);
+        if wi.z <= 0. {
+            return None;
+        }
+        let f = fresnel_conductor(Vec3::dot(wo, h).max(0.), self.eta, self.k);
+        Some(BsdfSample { wi, weight: self.tint * f * smith_g1(wi, self.alpha), pdf: self.pdf(wo, wi), specular: false })
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        if is_smooth(self.alpha) || wo.z <= 0. || wi.z <= 0. {
+            return 0.;
+        }
+        let h = Vec3::unit_vec(wo + wi);
+        vndf_pdf(wo, h, self.alpha) / (4. * Vec3::dot(wo, h))
+    }
+
+    fn is_specular(&self) -> bool {
+        is_smooth(self.alpha)
+    }
+}
+
+/*
+Principled BSDF, loosely following Disney's 2012 model and glTF's PBR extensions.
+
+Lobes: a Lambert diffuse base with sheen at grazing angles, a GGX specular whose reflectance
+blends from dielectric (`specular`) to the base color as `metallic` goes up, a rough
+dielectric transmission that takes the place of the diffuse as `transmission` goes up, and a
+clear coat layered on top of everything. Lobes are sampled in proportion to a rough guess of
+how much they reflect from wo, the pdf is the mixture of all of them.
+*/
+pub struct PrincipledBsdf {
+    base_color: Vec3,
+    alpha: f32,
+    //dielectric F0 and the metallic blended one
+    f0_dielectric: f32,
+    f0: Vec3,
+    diffuse: f32,
+    sheen: f32,
+    transmission: f32,
+    clearcoat: f32,
+    clearcoat_alpha: f32,
+    //inside index over outside index as seen from wo
+    etap: f32,
+    has_specular: bool
+}
+
+const DIFFUSE: usize = 0;
+const SPECULAR: usize = 1;
+const TRANSMISSION: usize = 2;
+const CLEARCOAT: usize = 3;
+
+impl PrincipledBsdf {
+    pub fn new(p: &Principled, front_face: bool) -> PrincipledBsdf {
+        let metallic = clamp(p.metallic, 0., 1.);
+        let transmission = clamp(p.transmission, 0., 1.);
+        let f0_dielectric = 0.08 * clamp(p.specular, 0., 1.);
+        PrincipledBsdf {
+            base_color: p.base_color,
+            alpha: roughness_to_alpha(clamp(p.roughness, 0., 1.)),
+            f0_dielectric,
+            f0: Vec3::new(f0_dielectric, f0_dielectric, f0_dielectric) * (1. - metallic) + p.base_color * metallic,
+            diffuse: (1. - metallic) * (1. - transmission),
+            sheen: clamp(p.sheen, 0., 1.),
+            transmission: (1. - metallic) * transmission,
+            clearcoat: clamp(p.clearcoat, 0., 1.),
+            clearcoat_alpha: roughness_to_alpha(clamp(p.clearcoat_roughness, 0., 1.)),
+            etap: if front_face { p.ior } else { 1. / p.ior },
+            has_specular: metallic > 0. || p.specular > 0.
+        }
+    }
+
+    // Odds of sampling each lobe from `wo`, summing to 1 (or all 0 for a black surface).
+    fn lobe_weights(&self, wo: Vec3) -> [f32; 4] {
+        let mut w = [0.; 4];
+        if self.diffuse > 0. {
+            w[DIFFUSE] = (self.diffuse * (luminance(self.base_color) + self.sheen)).max(1e-3);
+        }
+        if self.has_specular {
+            w[SPECULAR] = luminance(schlick(self.f0, wo.z)).max(1e-3);
+        }
+        if self.transmission > 0. {
+            let fresnel = fresnel_dielectric(clamp(wo.z, 0., 1.), 1. / self.etap);
+            //past the critical angle all of it reflects
+            let through = if fresnel >= 1. { 1. } else { luminance(self.base_color) * (1. - fresnel) };
+            w[TRANSMISSION] = (self.transmission * through).max(1e-3);
+        }
+        if self.clearcoat > 0. {
+            w[CLEARCOAT] = self.clearcoat * schlick(Vec3::new(0.04, 0.04, 0.04), wo.z).x;
+        }
+        let sum: f32 = w.iter().sum();
+        if sum > 0. {
+            for x in w.iter_mut() {
+                *x /= sum;
+            }
+        }
+        w
+    }
+
+    // How much the clear coat lets through to the layers below.
+    fn coat_transmittance(&self, cos: f32) -> f32 {
+        1. - self.clearcoat * schlick(Vec3::new(0.04, 0.04, 0.04), cos).x
+    }
+
+    // The share of light the specular lobe doesn't reflect, all of which total internal
+    // reflection sends back.
+    fn left_by_specular(&self, cos: f32) -> Vec3 {
+        let one = Vec3::new(1., 1., 1.);
+        if self.has_specular { one - schlick(self.f0, cos) } else { one }
+    }
+
+    // Microfacet normal for refraction between wo and wi, facing wo's side.
+    fn transmission_half(&self, wo: Vec3, wi: Vec3) -> Option<Vec3> {
+        let h = wi * self.etap + wo;
+        if h.length_squared() == 0. {
+            return None;
+        }
+        let h = Vec3::unit_vec(h);
+        let h = if h.z < 0. { h * -1. } else { h };
+        //both directions have to see the same microfacet from its two sides
+        if Vec3::dot(h, wo) <= 0. || Vec3::dot(h, wi) >= 0. {
+            return None;
+        }
+        Some(h)
+    }
+}
+
+impl Bsdf for PrincipledBsdf {
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        let mut f = Vec3::new(0., 0., 0.);
+        if wo.z <= 0. || wi.z == 0. {
+            return f;
+        }
+
+        if wi.z > 0. {
+            let h = Vec3::unit_vec(wo + wi);
+            let cos_d = Vec3::dot(wi, h);
+            if self.diffuse > 0. {
+                let kd = if self.has_specular { 1. - schlick(Vec3::new(self.f0_dielectric, self.f0_dielectric, self.f0_dielectric), cos_d).x } else { 1. };
+                let sheen = self.sheen * (1. - cos_d).powi(5);
+                f = f + (self.base_color * (kd / PI) + Vec3::new(sheen, sheen, sheen)) * self.diffuse;
+            }
+            if self.has_specular && !is_smooth(self.alpha) {
+                f = f + schlick(self.f0, cos_d) * (ggx_d(h, self.alpha) * smith_g2(wo, wi, self.alpha) / (4. * wo.z * wi.z));
+            }
+            //microfacets past the critical angle send the transmission back up
+            if self.transmission > 0. && !is_smooth(self.alpha) && fresnel_dielectric(cos_d, 1. / self.etap) >= 1. {
+                f = f + self.left_by_specular(cos_d) * (self.transmission * ggx_d(h, self.alpha) * smith_g2(wo, wi, self.alpha) / (4. * wo.z * wi.z));
+            }
+            f = f * self.coat_transmittance(cos_d);
+            if self.clearcoat > 0. && !is_smooth(self.clearcoat_alpha) {
+                let fc = schlick(Vec3::new(0.04, 0.04, 0.04), cos_d).x;
+                let c = self.clearcoat * fc * ggx_d(h, self.clearcoat_alpha) * smith_g2(wo, wi, self.clearcoat_alpha) / (4. * wo.z * wi.z);
+                f = f + Vec3::new(c, c, c);
+            }
+            return f;
+        }
+
+        if self.transmission > 0. && !is_smooth(self.alpha) {
+            let h = match self.transmission_half(wo, wi) {
+                Some(h) => h,
+                None => return f,
+            };
+            let (cos_o, cos_i) = (Vec3::dot(wo, h), Vec3::dot(wi, h));
+            let fresnel = fresnel_dielectric(cos_o, 1. / self.etap);
+            let denom = cos_i + cos_o / self.etap;
+            let t = (1. - fresnel) * ggx_d(h, self.alpha) * smith_g2(wo, flip(wi), self.alpha)
+                * (cos_i * cos_o / (denom * denom * wi.z * wo.z)).abs();
+            f = self.base_color * (t * self.transmission * self.coat_transmittance(wo.z));
+        }
+        f
+    }
+
+    fn sample(&self, wo: Vec3, uc: f32, a: f32, b: f32) -> Option<BsdfSample> {
+        if wo.z <= 0. {
+            return None;
+        }
+        let w = self.lobe_weights(wo);
+        let mut lobe = 0;
+        let mut cdf = w[0];
+        while lobe < 3 && uc >= cdf {
+            lobe += 1;
+            cdf += w[lobe];
+        }
+        if w[lobe] <= 0. {
+            return None;
+        }
+
+        let mut through = lobe == TRANSMISSION;
+        let wi = match lobe {
+            DIFFUSE => cosine_hemisphere(a, b),
+            SPECULAR if is_smooth(self.alpha) => {
+                let f = schlick(self.f0, wo.z) * (self.coat_transmittance(wo.z) / w[SPECULAR]);
+                return Some(BsdfSample { wi: mirror(wo), weight: f, pdf: 0., specular: true });
+            }
+            SPECULAR => reflect(wo, sample_vndf(wo, self.alpha, a, b)),
+            TRANSMISSION if is_smooth(self.alpha) => {
+                let fresnel = fresnel_dielectric(clamp(wo.z, 0., 1.), 1. / self.etap);
+                let wi = match refract(wo, Vec3::new(0., 0., 1.), 1. / self.etap) {
+                    Some(wi) => wi,
+                    //total internal reflection, like the dielectric's fresnel of 1
+                    None => {
+                        let f = self.left_by_specular(wo.z) * (self.transmission * self.coat_transmittance(wo.z) / w[TRANSMISSION]);
+                        return Some(BsdfSample { wi: mirror(wo), weight: f, pdf: 0., specular: true });
+                    }
+                };
+                let f = self.base_color * ((1. - fresnel) * self.transmission * self.coat_transmittance(wo.z) / w[TRANSMISSION]);
+                return Some(BsdfSample { wi, weight: f, pdf: 0., specular: true });
+            }
+            TRANSMISSION => {
+                let h = sample_vndf(wo, self.alpha, a, b);
+                refract(wo, h, 1. / self.etap).unwrap_or_else(|| {
+                    through = false;
+                    reflect(wo, h)
+                })
+            }
+            _ if is_smooth(self.clearcoat_alpha) => {
+                let c = self.clearcoat * schlick(Vec3::new(0.04, 0.04, 0.04), wo.z).x / w[CLEARCOAT];
+                return Some(BsdfSample { wi: mirror(wo), weight: Vec3::new(c, c, c), pdf: 0., specular: true });
+            }
+            _ => reflect(wo, sample_vndf(wo, self.clearcoat_alpha, a, b)),
+        };
+        //a reflection lobe that ended up below the surface or the other way around
+        if through != (wi.z < 0.) {
+            return None;
+        }
+        sampled(self, wo, wi)
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        if wo.z <= 0. || wi.z == 0. {
+            return 0.;
+        }
+        let w = self.lobe_weights(wo);
+        let mut pdf = 0.;
+        if wi.z > 0. {
+            let h = Vec3::unit_vec(wo + wi);
+            pdf += w[DIFFUSE] * wi.z / PI;
+            if !is_smooth(self.alpha) {
+                pdf += w[SPECULAR] * vndf_pdf(wo, h, self.alpha) / (4. * Vec3::dot(wo, h));
+                if fresnel_dielectric(Vec3::dot(wo, h), 1. / self.etap) >= 1. {
+                    pdf += w[TRANSMISSION] * vndf_pdf(wo, h, self.alpha) / (4. * Vec3::dot(wo, h));
+                }
+            }
+            if !is_smooth(self.clearcoat_alpha) {
+                pdf += w[CLEARCOAT] * vndf_pdf(wo, h, self.clearcoat_alpha) / (4. * Vec3::dot(wo, h));
+            }
+        } else if !is_smooth(self.alpha) {
+            if let Some(h) = self.transmission_half(wo, wi) {
+                let denom = Vec3::dot(wi, h) + Vec3::dot(wo, h) / self.etap;
+                pdf += w[TRANSMISSION] * vndf_pdf(wo, h, self.alpha) * Vec3::dot(wi, h).abs() / (denom * denom);
+            }
+        }
+        pdf
+    }
+
+    fn is_specular(&self) -> bool {
+        self.diffuse <= 0.
+            && (!self.has_specular || is_smooth(self.alpha))
+            && (self.transmission <= 0. || is_smooth(self.alpha))
+            && (self.clearcoat <= 0. || is_smooth(self.clearcoat_alpha))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Share of the light from `wo` the BSDF keeps, averaged over a grid of samples.
+    fn albedo(bsdf: &dyn Bsdf, wo: Vec3) -> f32 {
+        let n = 32;
+        let mut sum = 0.;
+        for i in 0..n {
+            for j in 0..n {
+                for k in 0..n {
+                    let (uc, a, b) = ((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32, (k as f32 + 0.5) / n as f32);
+                    if let Some(s) = bsdf.sample(wo, uc, a, b) {
+                        sum += s.weight.y;
+                    }
+                }
+            }
+        }
+        sum / (n * n * n) as f32
+    }
+
+    #[test]
+    fn principled_glass_reflects_past_the_critical_angle() {
+        //70 degrees from the normal inside glass of index 1.5, past the critical angle
+        let sin = 70f32.to_radians().sin();
+        let wo = Vec3::new(sin, 0., (1. - sin * sin).sqrt());
+        for roughness in [0., 0.3] {
+            let p = Principled { base_color: Vec3::new(1., 1., 1.), roughness, transmission: 1., ior: 1.5, ..Principled::default() };
+            //a white metal just as rough loses the same to single scattering and nothing else
+            let m = Principled { base_color: Vec3::new(1., 1., 1.), roughness, metallic: 1., ..Principled::default() };
+            let (glass, metal) = (albedo(&PrincipledBsdf::new(&p, false), wo), albedo(&PrincipledBsdf::new(&m, true), wo));
+            assert!((glass - metal).abs() < 0.02, "roughness {}: glass {} metal {}", roughness, glass, metal);
+        }
+    }
+}
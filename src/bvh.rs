@@ -0,0 +1,301 @@
+use std::cell::Cell;
+use std::fmt;
+
+use crate::aabb::*;
+use crate::hittable::*;
+use crate::ray::*;
+use crate::vec3::*;
+
+//Binned SAH build. More bins = better trees, slower builds.
+const SAH_BINS: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+//cost of one traversal step relative to one primitive intersection
+const TRAVERSAL_COST: f32 = 1.;
+
+// Flattened node. Leaves own objects[start..start + count], interior nodes have
+// their left child right after them and the right child at `right`.
+#[derive(Clone, Copy)]
+struct BvhNode {
+    bounds: Aabb,
+    start: usize,
+    count: usize,
+    right: usize,
+    axis: usize
+}
+
+pub struct Bvh {
+    pub objects: Vec<Box<dyn Hittable>>,
+    nodes: Vec<BvhNode>
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BvhStats {
+    pub node_count: usize,
+    pub leaf_count: usize,
+    pub max_depth: usize,
+    pub min_leaf_size: usize,
+    pub max_leaf_size: usize,
+    pub avg_leaf_size: f32
+}
+
+impl fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BVH: {} nodes, {} leaves, depth {}, leaf size min {} / max {} / avg {:.2}",
+               self.node_count, self.leaf_count, self.max_depth, self.min_leaf_size, self.max_leaf_size, self.avg_leaf_size)
+    }
+}
+
+struct BuildItem {
+    bounds: Aabb,
+    centroid: Vec3,
+    index: usize
+}
+
+impl Bvh {
+    // Every object must have a bounding box; unbounded shapes stay outside the tree.
+    pub fn new(objects: Vec<Box<dyn Hittable>>) -> Bvh {
+        let mut items: Vec<BuildItem> = objects.iter().enumerate().map(|(index, o)| {
+            let bounds = o.bounding_box().expect("BVH objects must be bounded");
+            BuildItem { bounds, centroid: bounds.centroid(), index }
+        }).collect();
+
+        let mut nodes = vec![];
+        if !items.is_empty() {
+            let count = items.len();
+            build(&mut nodes, &mut items, 0, count);
+        }
+
+        //reorder the objects to match the leaf ranges
+        let mut slots: Vec<Option<Box<dyn Hittable>>> = objects.into_iter().map(Some).collect();
+        let objects = items.iter().map(|item| slots[item.index].take().unwrap()).collect();
+
+        Bvh { objects, nodes }
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let closest = Cell::new(t_max);
+        let mut record = None;
+        self.traverse(ray, t_min, &closest, |object| {
+            if let Some(rec) = object.hit(ray, t_min, closest.get()) {
+                closest.set(rec.t);
+                record = Some(rec);
+            }
+            false
+        });
+        record
+    }
+
+    // Any-hit query for shadow rays; stops at the first intersection found.
+    pub fn occluded(&self, ray: &Ray, t_min: f32, t_max: f32) -> bool {
+        let mut found = false;
+        self.traverse(ray, t_min, &Cell::new(t_max), |object| {
+            found = object.hit(ray, t_min, t_max).is_some();
+            found
+        });
+        found
+    }
+
+    // Walks the tree front to back. `t_max` is re-read at every node so closest-hit queries
+    // can shrink it, and `visit` returns true to stop early.
+    fn traverse<'a, V>(&'a self, ray: &Ray, t_min: f32, t_max: &Cell<f32>, mut visit: V)
+    where V: FnMut(&'a dyn Hittable) -> bool {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let dir = ray.direction();
+        let inv_dir = Vec3::new(1. / dir.x, 1. / dir.y, 1. / dir.z);
+        let mut stack = vec![0usize];
+        while let Some(i) = stack.pop() {
+            let node = &self.nodes[i];
+            if !node.bounds.hit(ray, inv_dir, t_min, t_max.get()) {
+                continue;
+            }
+            if node.count > 0 {
+                for object in &self.objects[node.start..node.start + node.count] {
+                    if visit(object.as_ref()) {
+                        return;
+                    }
+                }
+            } else if dir[node.axis] < 0. {
+                stack.push(i + 1);
+                stack.push(node.right);
+            } else {
+                stack.push(node.right);
+                stack.push(i + 1);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> BvhStats {
+        let mut stats = BvhStats { node_count: self.nodes.len(), min_leaf_size: usize::MAX, ..BvhStats::default() };
+        if self.nodes.is_empty() {
+            stats.min_leaf_size = 0;
+            return stats;
+        }
+        let mut stack = vec![(0usize, 1usize)];
+        let mut total = 0;
+        while let Some((i, depth)) = stack.pop() {
+            let node = &self.nodes[i];
+            stats.max_depth = stats.max_depth.max(depth);
+            if node.count > 0 {
+                stats.leaf_count += 1;
+                stats.min_leaf_size = stats.min_leaf_size.min(node.count);
+                stats.max_leaf_size = stats.max_leaf_size.max(node.count);
+                total += node.count;
+            } else {
+                stack.push((i + 1, depth + 1));
+                stack.push((node.right, depth + 1));
+            }
+        }
+        stats.avg_leaf_size = total as f32 / stats.leaf_count as f32;
+        stats
+    }
+}
+
+// Builds the subtree for items[start..end] and returns its node index.
+fn build(nodes: &mut Vec<BvhNode>, items: &mut [BuildItem], start: usize, end: usize) -> usize {
+    let index = nodes.len();
+    let slice = &mut items[start..end];
+    let bounds = slice.iter().fold(Aabb::empty(), |b, item| b.union(item.bounds));
+    let centroid_bounds = slice.iter().fold(Aabb::empty(), |b, item| b.grow(item.centroid));
+    let count = end - start;
+    nodes.push(BvhNode { bounds, start, count, right: 0, axis: 0 });
+
+    if count <= 1 {
+        return index;
+    }
+
+    let (axis, split) = match find_split(slice, bounds, centroid_bounds) {
+        Some(s) => s,
+        None if count <= MAX_LEAF_SIZE => return index,
+        //SAH found nothing worth splitting but the leaf is too big: fall back to a median split
+        None => {
+            let axis = centroid_bounds.largest_axis();
+            slice.sort_by(|a, b| a.centroid[axis].total_cmp(&b.centroid[axis]));
+            (axis, count / 2)
+        }
+    };
+
+    let mid = start + split;
+
+    nodes[index].count = 0;
+    nodes[index].axis = axis;
+    build(nodes, items, start, mid);
+    let right = build(nodes, items, mid, end);
+    nodes[index].right = right;
+    index
+}
+
+// Evaluates SAH_BINS - 1 candidate planes on each axis and partitions the items in place
+// around the cheapest one. Returns None when a leaf is cheaper than any split.
+fn find_split(items: &mut [BuildItem], bounds: Aabb, centroid_bounds: Aabb) -> Option<(usize, usize)> {
+    let count = items.len();
+    let leaf_cost = count as f32;
+    let mut best: Option<(usize, usize, f32)> = None;
+
+    for axis in 0..3 {
+        let lo = centroid_bounds.min[axis];
+        let extent = centroid_bounds.max[axis] - lo;
+        if extent <= 0. {
+            continue;
+        }
+        let bin_of = |c: f32| (((c - lo) / extent * SAH_BINS as f32) as usize).min(SAH_BINS - 1);
+
+        let mut bin_bounds = [Aabb::empty(); SAH_BINS];
+        let mut bin_counts = [0usize; SAH_BINS];
+        for item in items.iter() {
+            let b = bin_of(item.centroid[axis]);
+            bin_counts[b] += 1;
+            bin_bounds[b] = bin_bounds[b].union(item.bounds);
+        }
+
+        //sweep from the right to get the area and count of every right half
+        let mut right_area = [0f32; SAH_BINS];
+        let mut right_count = [0usize; SAH_BINS];
+        let mut acc = Aabb::empty();
+        let mut n = 0;
+        for b in (1..SAH_BINS).rev() {
+            acc = acc.union(bin_bounds[b]);
+            n += bin_counts[b];
+            right_area[b] = acc.surface_area();
+            right_count[b] = n;
+        }
+
+        let mut acc = Aabb::empty();
+        let mut n = 0;
+        for b in 0..SAH_BINS - 1 {
+            acc = acc.union(bin_bounds[b]);
+            n += bin_counts[b];
+            if n == 0 || right_count[b + 1] == 0 {
+                continue;
+            }
+            let cost = TRAVERSAL_COST + (acc.surface_area() * n as f32 + right_area[b + 1] * right_count[b + 1] as f32) / bounds.surface_area();
+            if best.is_none_or(|(_, _, c)| cost < c) {
+                best = Some((axis, b, cost));
+            }
+        }
+    }
+
+    let (axis, bin, cost) = best?;
+    if cost >= leaf_cost && count <= MAX_LEAF_SIZE {
+        return None;
+    }
+
+    let lo = centroid_bounds.min[axis];
+    let extent = centroid_bounds.max[axis] - lo;
+    let bin_of = |c: f32| (((c - lo) / extent * SAH_BINS as f32) as usize).min(SAH_BINS - 1);
+    let mut split = 0;
+    for i in 0..count {
+        if bin_of(items[i].centroid[axis]) <= bin {
+            items.swap(i, split);
+            split += 1;
+        }
+    }
+    Some((axis, split))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::*;
+    use crate::primitives::*;
+
+    // Scattered spheres and triangles, some of them overlapping.
+    fn shapes() -> Vec<Box<dyn Hittable>> {
+        let mut state = 0x2545f491u32;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as f32 / u32::MAX as f32 * 10. - 5.
+        };
+        let material = || Material::new(Vec3::new(0.5, 0.5, 0.5), 0., 0.);
+        let mut shapes: Vec<Box<dyn Hittable>> = vec![];
+        for _ in 0..60 {
+            let center = Vec3::new(next(), next(), next());
+            shapes.push(Box::new(Sphere::new(center, (next() + 5.) * 0.05 + 0.05, material())));
+            let (a, b) = (Vec3::new(next(), next(), next()) * 0.2, Vec3::new(next(), next(), next()) * 0.2);
+            shapes.push(Box::new(Triangle::new(center, center + a, center + b, material())));
+        }
+        shapes
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let bvh = Bvh::new(shapes());
+        let all = shapes();
+        for i in 0..2000 {
+            //rays from a sphere around the scene toward points inside it
+            let k = i as f32;
+            let origin = Vec3::new((k * 0.37).sin(), (k * 0.71).cos(), (k * 0.13).sin()) * 12.;
+            let target = Vec3::new((k * 1.9).sin(), (k * 2.3).cos(), (k * 3.1).sin()) * 5.;
+            let ray = Ray::new(origin, Vec3::unit_vec(target - origin));
+            let t_max = if i % 2 == 0 { f32::INFINITY } else { 15. };
+
+            let nearest = all.iter().filter_map(|s| s.hit(&ray, 0.001, t_max)).map(|rec| rec.t).fold(None, |best: Option<f32>, t| Some(best.map_or(t, |b| b.min(t))));
+            assert_eq!(bvh.hit(&ray, 0.001, t_max).map(|rec| rec.t), nearest, "ray {}", i);
+            assert_eq!(bvh.occluded(&ray, 0.001, t_max), nearest.is_some(), "ray {}", i);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::f32::consts::PI;
+
+use crate::vec3::*;
+use crate::ray::*;
+use crate::sampling::*;
+
+// Thin lens camera at `look_from` pointed at `look_at`. `vfov` is the vertical field of
+// view in degrees and `vup` picks which way is up in the image. Rays start on a lens of
+// diameter `aperture` and converge on the plane `focus_dist` away, so anything off that
+// plane blurs. An aperture of 0 is a pinhole and everything is sharp.
+#[derive(Clone, Copy)]
+pub struct Camera {
+    pub origin: Vec3,
+    pub lower_left_corner: Vec3,
+    pub horizontal: Vec3,
+    pub vertical: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub lens_radius: f32,
+    //0 = round lens, 3 or more = polygonal aperture (bokeh shape)
+    pub blades: u32,
+    pub blade_rotation: f32
+}
+
+impl Camera {
+    pub fn new(look_from: Vec3, look_at: Vec3, vup: Vec3, vfov: f32, aspect_ratio: f32, aperture: f32, focus_dist: f32) -> Camera {
+        let h = (vfov.to_radians() / 2.).tan();
+        let viewport_height = 2. * h;
+        let viewport_width = aspect_ratio * viewport_height;
+
+        //camera basis: w points backwards, u right, v up
+        let w = Vec3::unit_vec(look_from - look_at);
+        let u = Vec3::unit_vec(Vec3::cross(vup, w));
+        let v = Vec3::cross(w, u);
+
+        //the viewport sits on the focus plane
+        let origin = look_from;
+        let horizontal = u * viewport_width * focus_dist;
+        let vertical = v * viewport_height * focus_dist;
+        let lower_left_corner = origin - horizontal/2. - vertical/2. - w * focus_dist;
+        Camera { origin, lower_left_corner, horizontal, vertical, u, v, lens_radius: aperture / 2., blades: 0, blade_rotation: 0. }
+    }
+
+    // Polygonal aperture with `blades` sides, rotated by `rotation` degrees.
+    pub fn with_blades(mut self, blades: u32, rotation: f32) -> Camera {
+        self.blades = blades;
+        self.blade_rotation = rotation.to_radians();
+        self
+    }
+
+    // s and t go from 0 to 1 across the image, bottom left to top right. `lens` picks the
+    // point on the aperture.
+    pub fn get_ray(&self, s: f32, t: f32, lens: (f32, f32)) -> Ray {
+        let (lx, ly) = if self.lens_radius > 0. { self.sample_lens(lens.0, lens.1) } else { (0., 0.) };
+        let offset = self.u * (lx * self.lens_radius) + self.v * (ly * self.lens_radius);
+        let origin = self.origin + offset;
+        Ray::new(origin, self.lower_left_corner + self.horizontal * s + self.vertical * t - origin)
+    }
+
+    // Uniform point on the unit aperture: a disk, or a regular polygon inscribed in it.
+    fn sample_lens(&self, a: f32, b: f32) -> (f32, f32) {
+        if self.blades < 3 {
+            return concentric_disk(a, b);
+        }
+
+        //pick one of the polygon's triangle wedges with a, then reuse what's left of it
+        let n = self.blades as f32;
+        let wedge = (a * n).floor().min(n - 1.);
+        let a = a * n - wedge;
+        let theta0 = self.blade_rotation + wedge * 2. * PI / n;
+        let theta1 = theta0 + 2. * PI / n;
+        let (mut a, mut b) = (a, b);
+        if a + b > 1. {
+            a = 1. - a;
+            b = 1. - b;
+        }
+        (a * theta0.cos() + b * theta1.cos(), a * theta0.sin() + b * theta1.sin())
+    }
+}
@@ -0,0 +1,159 @@
+use std::f32::consts::PI;
+use std::path::Path;
+
+use crate::vec3::*;
+use crate::util::*;
+use crate::light::*;
+use crate::texture::*;
+use crate::sampling::*;
+use crate::sky::*;
+
+/*
+What rays that leave the scene see, and the light it sends back in.
+
+Gradient is the original orange to blue sky. It isn't sampled as a light, escaping paths
+pick it up on their own.
+
+Map is an equirectangular HDR image wrapped around the scene: the top row is straight up,
+the middle of the image looks down -z and `rotation` spins it around +y (degrees).
+It's importance sampled by luminance so small bright spots like the sun get found.
+
+Sky is the analytic daylight model, sampled the same way, with its sun handed to the world
+as a distant light.
+*/
+pub enum Environment {
+    Gradient,
+    Map(EnvironmentMap),
+    Sky(PhysicalSky)
+}
+
+impl Environment {
+    // Radiance arriving from infinitely far away along -`dir`, i.e. seen looking along `dir`.
+    pub fn radiance(&self, dir: Vec3) -> Vec3 {
+        match self {
+            Environment::Gradient => gradient(dir),
+            Environment::Map(map) => map.radiance(dir),
+            Environment::Sky(sky) => sky.radiance(dir),
+        }
+    }
+
+    // Whether direct lighting samples it, and so whether BSDF bounces must leave it alone.
+    pub fn is_sampled(&self) -> bool {
+        !matches!(self, Environment::Gradient)
+    }
+
+    pub fn sample(&self, a: f32, b: f32) -> Option<LightSample> {
+        match self {
+            Environment::Gradient => None,
+            Environment::Map(map) => map.sample(a, b),
+            Environment::Sky(sky) => sky.sample(a, b),
+        }
+    }
+
+    // Solid angle density of `sample` choosing `dir`.
+    pub fn pdf(&self, dir: Vec3) -> f32 {
+        match self {
+            Environment::Gradient => 0.,
+            Environment::Map(map) => map.pdf(dir),
+            Environment::Sky(sky) => sky.pdf(dir),
+        }
+    }
+
+    // Like shapes, the environment can come with a light of its own.
+    pub fn emitter(&self) -> Option<Box<dyn Light>> {
+        match self {
+            Environment::Sky(sky) => sky.sun_light(),
+            _ => None,
+        }
+    }
+}
+
+fn gradient(dir: Vec3) -> Vec3 {
+    let unit_dir: Vec3 = Vec3::unit_vec(dir);
+    let t: f32 = 0.5 * (unit_dir.y + 1.0);
+
+    Vec3::new(1. ,0.7 ,0.5) * (1.0 - t) + Vec3::new(0.5, 0.7, 1.0) * t
+}
+
+pub struct EnvironmentMap {
+    pub image: Image,
+    pub intensity: f32,
+    //radians
+    pub rotation: f32,
+    distribution: Distribution2D
+}
+
+impl EnvironmentMap {
+    pub fn load(path: &Path, intensity: f32, rotation: f32) -> Result<EnvironmentMap, String> {
+        //around the horizon wraps, the poles don't meet each other
+        let image = Image::load(path, true, Wrap::Repeat)?.with_wrap_v(Wrap::Clamp);
+        Ok(EnvironmentMap::new(image, intensity, rotation))
+    }
+
+    pub fn new(image: Image, intensity: f32, rotation: f32) -> EnvironmentMap {
+        let (width, height) = (image.width, image.height);
+        //rows near the poles cover less of the sphere. The small floor keeps the pdf above zero
+        //wherever bilinear filtering can bleed light into a black texel.
+        let mut func: Vec<f32> = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let sin_theta = (PI * (y as f32 + 0.5) / height as f32).sin();
+            func.extend(image.pixels[y * width..(y + 1) * width].iter().map(|&c| luminance(c).max(0.) * sin_theta));
+        }
+        let average = func.iter().sum::<f32>() / func.len().max(1) as f32;
+        for f in &mut func {
+            *f += average * 1e-3 + 1e-8;
+        }
+        let distribution = Distribution2D::new(&func, width, height);
+        EnvironmentMap { image, intensity, rotation: rotation.to_radians(), distribution }
+    }
+
+    pub fn radiance(&self, dir: Vec3) -> Vec3 {
+        let (x, y) = self.map_position(Vec3::unit_vec(dir));
+        //Texture uv count v up from the bottom
+        self.image.value(x, 1. - y, dir) * self.intensity
+    }
+
+    pub fn sample(&self, a: f32, b: f32) -> Option<LightSample> {
+        let ((x, y), pdf_map) = self.distribution.sample(a, b);
+        let theta = y * PI;
+        let sin_theta = theta.sin();
+        if pdf_map <= 0. || sin_theta <= 0. {
+            return None;
+        }
+        let wi = self.direction(x, y);
+        //the map spans 2 PI by PI radians, sin(theta) shrinks the rows toward the poles
+        let pdf = pdf_map / (2. * PI * PI * sin_theta);
+        Some(LightSample { wi, dist: f32::INFINITY, weight: self.radiance(wi) / pdf, pdf })
+    }
+
+    pub fn pdf(&self, dir: Vec3) -> f32 {
+        let (x, y) = self.map_position(Vec3::unit_vec(dir));
+        let sin_theta = (y * PI).sin();
+        if sin_theta <= 0. {
+            return 0.;
+        }
+        self.distribution.pdf(x, y) / (2. * PI * PI * sin_theta)
+    }
+
+    fn map_position(&self, dir: Vec3) -> (f32, f32) {
+        let (x, y) = equirect_position(dir);
+        ((x - self.rotation / (2. * PI)).rem_euclid(1.), y)
+    }
+
+    fn direction(&self, x: f32, y: f32) -> Vec3 {
+        equirect_direction(x + self.rotation / (2. * PI), y)
+    }
+}
+
+// Image position (x right, y down, both 0 to 1) of a unit direction.
+pub fn equirect_position(dir: Vec3) -> (f32, f32) {
+    let x = (dir.x.atan2(-dir.z) / (2. * PI) + 0.5).rem_euclid(1.);
+    let y = clamp(dir.y, -1., 1.).acos() / PI;
+    (x, y)
+}
+
+pub fn equirect_direction(x: f32, y: f32) -> Vec3 {
+    let phi = (x - 0.5) * 2. * PI;
+    let theta = y * PI;
+    Vec3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos())
+}
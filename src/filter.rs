@@ -0,0 +1,197 @@
+use std::f32::consts::PI;
+
+/*
+Reconstruction filters. Every sample is splatted onto the pixels whose centers lie within
+`radius` of it (in pixels), weighted by the filter at the offset, and each pixel ends up as
+its weighted average. All of them are separable, the 2D weight is f(x) * f(y).
+
+    box       1 inside the radius. With the default radius of 0.5 a pixel averages just
+              its own samples.
+    tent      falls off linearly to 0 at the radius
+    gaussian  sigma of a third of the radius, shifted down to reach 0 at the edge
+    mitchell  Mitchell-Netravali with B = C = 1/3, sharper than a gaussian with only a
+              slight negative ring
+    lanczos   sinc windowed by a wider sinc, as many lobes as the radius. Sharpest, but it
+              rings around hard edges
+*/
+pub trait Filter: Send + Sync {
+    fn radius(&self) -> f32;
+
+    // Weight of a sample offset by (x, y) pixels from a pixel center.
+    fn evaluate(&self, x: f32, y: f32) -> f32;
+}
+
+pub struct BoxFilter {
+    pub radius: f32
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f32, y: f32) -> f32 {
+        //half open, so a sample on a pixel edge only lands in one pixel
+        if (-self.radius..self.radius).contains(&x) && (-self.radius..self.radius).contains(&y) { 1. } else { 0. }
+    }
+}
+
+pub struct TentFilter {
+    pub radius: f32
+}
+
+impl Filter for TentFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f32, y: f32) -> f32 {
+        (self.radius - x.abs()).max(0.) * (self.radius - y.abs()).max(0.)
+    }
+}
+
+pub struct GaussianFilter {
+    pub radius: f32,
+    sigma: f32,
+    //value at the radius, taken off so the filter ends at 0
+    edge: f32
+}
+
+impl GaussianFilter {
+    pub fn new(radius: f32) -> GaussianFilter {
+        let sigma = radius / 3.;
+        GaussianFilter { radius, sigma, edge: gaussian(radius, sigma) }
+    }
+
+    fn f(&self, x: f32) -> f32 {
+        (gaussian(x, self.sigma) - self.edge).max(0.)
+    }
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f32, y: f32) -> f32 {
+        self.f(x) * self.f(y)
+    }
+}
+
+fn gaussian(x: f32, sigma: f32) -> f32 {
+    (-x * x / (2. * sigma * sigma)).exp()
+}
+
+pub struct MitchellFilter {
+    pub radius: f32,
+    pub b: f32,
+    pub c: f32
+}
+
+impl MitchellFilter {
+    pub fn new(radius: f32) -> MitchellFilter {
+        MitchellFilter { radius, b: 1. / 3., c: 1. / 3. }
+    }
+
+    // The cubic is defined on [-2, 2], stretched to the radius.
+    fn f(&self, x: f32) -> f32 {
+        let x = (2. * x / self.radius).abs();
+        let (b, c) = (self.b, self.c);
+        let v = if x > 2. {
+            0.
+        } else if x > 1. {
+            (-b - 6. * c) * x * x * x + (6. * b + 30. * c) * x * x + (-12. * b - 48. * c) * x + (8. * b + 24. * c)
+        } else {
+            (12. - 9. * b - 6. * c) * x * x * x + (-18. + 12. * b + 6. * c) * x * x + (6. - 2. * b)
+        };
+        v / 6.
+    }
+}
+
+impl Filter for MitchellFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f32, y: f32) -> f32 {
+        self.f(x) * self.f(y)
+    }
+}
+
+pub struct LanczosFilter {
+    pub radius: f32
+}
+
+impl LanczosFilter {
+    fn f(&self, x: f32) -> f32 {
+        if x.abs() >= self.radius {
+            return 0.;
+        }
+        sinc(x) * sinc(x / self.radius)
+    }
+}
+
+impl Filter for LanczosFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f32, y: f32) -> f32 {
+        self.f(x) * self.f(y)
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-5 {
+        return 1.;
+    }
+    (PI * x).sin() / (PI * x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filters() -> Vec<(&'static str, Box<dyn Filter>)> {
+        vec![
+            ("box", Box::new(BoxFilter { radius: 0.5 })),
+            ("tent", Box::new(TentFilter { radius: 1. })),
+            ("gaussian", Box::new(GaussianFilter::new(1.5))),
+            ("mitchell", Box::new(MitchellFilter::new(2.))),
+            ("lanczos", Box::new(LanczosFilter { radius: 3. }))
+        ]
+    }
+
+    #[test]
+    fn symmetric() {
+        for (name, filter) in filters() {
+            let r = filter.radius();
+            for i in 0..20 {
+                let (x, y) = (r * i as f32 / 20., r * (19 - i) as f32 / 23.);
+                let w = filter.evaluate(x, y);
+                for (sx, sy) in [(-x, y), (x, -y), (-x, -y), (y, x)] {
+                    assert!((filter.evaluate(sx, sy) - w).abs() <= 1e-6, "{} at ({}, {})", name, sx, sy);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn zero_at_radius() {
+        for (name, filter) in filters() {
+            let r = filter.radius();
+            assert!(filter.evaluate(0., 0.) > 0., "{}", name);
+            for (x, y) in [(r, 0.), (0., r), (r, r), (r + 0.25, 0.), (0., -r - 0.25)] {
+                assert!(filter.evaluate(x, y).abs() < 1e-6, "{} at ({}, {})", name, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn box_edge_lands_in_one_pixel() {
+        let filter = BoxFilter { radius: 0.5 };
+        //a sample on the edge between two pixels is -0.5 from one center and 0.5 from the other
+        assert_eq!(filter.evaluate(-0.5, 0.) + filter.evaluate(0.5, 0.), 1.);
+        assert_eq!(filter.evaluate(0., -0.5) + filter.evaluate(0., 0.5), 1.);
+    }
+}
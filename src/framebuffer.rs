@@ -0,0 +1,89 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::vec3::*;
+use crate::util::*;
+
+// Linear, unclamped radiance for every pixel, top row first.
+#[derive(Clone)]
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Vec3>
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Framebuffer {
+        Framebuffer { width, height, pixels: vec![Vec3::new(0., 0., 0.); (width * height) as usize] }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Vec3 {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, color: Vec3) {
+        self.pixels[(y * self.width + x) as usize] = color;
+    }
+
+    // Picks the format from the extension: .png (8 bit sRGB), .exr and .pfm (32 bit float,
+    // linear) or .ppm (8 bit sRGB, plain text).
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+        let result = match ext.as_str() {
+            "png" => self.write_png(path),
+            "exr" => self.write_exr(path),
+            "pfm" => File::create(path).and_then(|f| self.write_pfm(&mut BufWriter::new(f))).map_err(|e| e.to_string()),
+            "ppm" => File::create(path).and_then(|f| self.write_ppm(&mut BufWriter::new(f))).map_err(|e| e.to_string()),
+            _ => Err(format!("unknown image format '.{}' (use .png, .exr, .pfm or .ppm)", ext)),
+        };
+        result.map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // 8 bit sRGB bytes, row by row.
+    fn to_srgb8(&self) -> Vec<u8> {
+        self.pixels.iter()
+            .flat_map(|c| [srgb8(c.x), srgb8(c.y), srgb8(c.z)])
+            .collect()
+    }
+
+    pub fn write_ppm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "P3\n{} {}\n{}", self.width, self.height, 255)?;
+        for rgb in self.to_srgb8().chunks(3) {
+            writeln!(out, "{} {} {}", rgb[0], rgb[1], rgb[2])?;
+        }
+        out.flush()
+    }
+
+    fn write_png(&self, path: &Path) -> Result<(), String> {
+        image::save_buffer(path, &self.to_srgb8(), self.width, self.height, image::ExtendedColorType::Rgb8)
+            .map_err(|e| e.to_string())
+    }
+
+    fn write_exr(&self, path: &Path) -> Result<(), String> {
+        let data: Vec<f32> = self.pixels.iter().flat_map(|c| [c.x, c.y, c.z]).collect();
+        let img = image::Rgb32FImage::from_raw(self.width, self.height, data).ok_or("framebuffer size mismatch")?;
+        img.save(path).map_err(|e| e.to_string())
+    }
+
+    // Portable float map: little endian (negative scale), rows stored bottom up.
+    pub fn write_pfm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let c = self.get(x, y);
+                for v in [c.x, c.y, c.z] {
+                    out.write_all(&v.to_le_bytes())?;
+                }
+            }
+        }
+        out.flush()
+    }
+}
+
+// Linear -> sRGB transfer curve, quantized to a byte.
+fn srgb8(linear: f32) -> u8 {
+    let x = clamp(linear, 0., 1.);
+    let s = if x <= 0.0031308 { 12.92 * x } else { 1.055 * x.powf(1. / 2.4) - 0.055 };
+    (s * 255. + 0.5) as u8
+}
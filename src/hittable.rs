@@ -0,0 +1,70 @@
+use crate::vec3::*;
+use crate::aabb::*;
+use crate::ray::*;
+use crate::light::*;
+use crate::material::*;
+use crate::sampling::*;
+
+// Everything the shader needs to know about a ray/surface intersection.
+// `normal` is the geometric normal and `shading_normal` the one used for lighting,
+// both flipped to face the incoming ray. `front_face` records whether the ray hit the outside.
+// (u, v) are the surface's texture coordinates and `dpdu`, `dpdv` how the point moves along
+// them, which is what orients normal and bump maps. `light` is the world's index of the light
+// standing in for an emissive surface, so BSDF samples that hit it can be weighed against it.
+#[derive(Clone, Copy)]
+pub struct HitRecord<'a> {
+    pub t: f32,
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub shading_normal: Vec3,
+    pub u: f32,
+    pub v: f32,
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    pub material: &'a Material,
+    pub front_face: bool,
+    pub light: Option<usize>
+}
+
+impl<'a> HitRecord<'a> {
+    pub fn new(ray: &Ray, t: f32, outward_normal: Vec3, u: f32, v: f32, material: &'a Material) -> HitRecord<'a> {
+        let front_face = Vec3::dot(ray.direction(), outward_normal) < 0.;
+        let normal = if front_face { outward_normal } else { outward_normal * -1. };
+        //any frame will do for shapes without a parametrization
+        let (dpdu, dpdv) = orthonormal_basis(outward_normal);
+        HitRecord { t, point: ray.at(t), normal, shading_normal: normal, u, v, dpdu, dpdv, material, front_face, light: None }
+    }
+
+    // Shading normal given on the outside, like `outward_normal`.
+    pub fn with_shading_normal(mut self, outward: Vec3) -> HitRecord<'a> {
+        self.shading_normal = if self.front_face { outward } else { outward * -1. };
+        self
+    }
+
+    pub fn with_derivatives(mut self, dpdu: Vec3, dpdv: Vec3) -> HitRecord<'a> {
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+        self
+    }
+
+    pub fn with_light(mut self, light: Option<usize>) -> HitRecord<'a> {
+        self.light = light;
+        self
+    }
+}
+
+pub trait Hittable: Send + Sync {
+    // Closest intersection with t in (t_min, t_max), or None on a miss.
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>>;
+
+    // World space bounds, or None for infinite shapes like planes.
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    // Shapes that glow can hand the world a light to sample.
+    fn emitter(&self) -> Option<Box<dyn Light>> {
+        None
+    }
+
+    // Where the world put that light, for the shape to report in its hits.
+    fn attach_light(&mut self, _index: usize) {}
+}
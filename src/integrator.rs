@@ -0,0 +1,112 @@
+use crate::vec3::*;
+use crate::ray::*;
+use crate::world::*;
+use crate::sampling::*;
+use crate::sampler::*;
+
+//bounces before russian roulette may start killing paths
+const RR_START_DEPTH: u32 = 3;
+
+/*
+Unidirectional path tracer.
+
+At every surface the material hands over its BSDF. Unless it's all mirror/glass, direct light
+is gathered with a shadow ray toward one of the lights (next event estimation), then the BSDF
+picks where the path goes next, which carries the indirect light. Lights the BSDF sample runs
+into were found both ways, so each way is weighed by the power heuristic (multiple importance
+sampling): light sampling wins on small lights and rough surfaces, BSDF sampling on big lights
+and glossy ones. Glass tints the path by absorption when it leaves. Normal and bump maps
+replace the shading normal before any of this.
+*/
+#[derive(Clone, Copy)]
+pub struct PathTracer {
+    pub max_depth: u32
+}
+
+impl PathTracer {
+    pub fn new(max_depth: u32) -> PathTracer {
+        PathTracer { max_depth }
+    }
+
+    // Radiance arriving along `ray`.
+    pub fn li(&self, world: &World, ray: &Ray, sampler: &mut dyn Sampler) -> Vec3 {
+        let mut radiance = Vec3::new(0., 0., 0.);
+        let mut throughput = Vec3::new(1., 1., 1.);
+        let mut ray = *ray;
+        //the bounce that made `ray`, None when light sampling couldn't have
+        let mut from: Option<Bounce> = None;
+
+        //one pass past the last bounce, to pick up the light its BSDF sample found
+        for depth in 0..=self.max_depth {
+            let mut rec = match world.hit(&ray, 0.0001, f32::INFINITY) {
+                Some(rec) => rec,
+                None => {
+                    radiance = radiance + throughput * world.escaped(&ray, from);
+                    break;
+                }
+            };
+            let material = rec.material;
+
+            if material.is_emissive() {
+                radiance = radiance + throughput * world.emission(&rec, &ray, from);
+                break;
+            }
+            if depth == self.max_depth {
+                break;
+            }
+
+            //leaving a medium, so the segment we just travelled was inside it
+            if let Some(absorption) = material.absorption() {
+                if !rec.front_face {
+                    let dist = (rec.point - ray.origin()).length();
+                    throughput = throughput * Vec3::new((-absorption.x * dist).exp(), (-absorption.y * dist).exp(), (-absorption.z * dist).exp());
+                }
+            }
+
+            let view = Vec3::unit_vec(ray.direction()) * -1.;
+            if let Some(map) = &material.normal_map {
+                rec.shading_normal = map.perturb(&rec, view);
+            }
+            //interpolated normals can turn away from the viewer near silhouettes
+            if Vec3::dot(rec.shading_normal, view) <= 0. {
+                rec.shading_normal = rec.normal;
+            }
+
+            //same dimensions at every bounce whichever branches it takes
+            let (light_pick, light_point) = (sampler.get_1d(), sampler.get_2d());
+            let (lobe, (a, b)) = (sampler.get_1d(), sampler.get_2d());
+            let survive = sampler.get_1d();
+
+            let bsdf = material.bsdf(&rec);
+            let n = rec.shading_normal;
+            let wo = to_local(view, n);
+
+            if !bsdf.is_specular() {
+                radiance = radiance + throughput * world.direct_light(&rec, light_pick, light_point, |wi| {
+                    let wi = to_local(wi, n);
+                    (bsdf.eval(wo, wi), bsdf.pdf(wo, wi))
+                });
+            }
+
+            let sample = match bsdf.sample(wo, lobe, a, b) {
+                Some(s) => s,
+                None => break,
+            };
+            throughput = throughput * sample.weight;
+            let wi = to_world(sample.wi, n);
+            //start on whichever side of the surface the new direction leaves from
+            let side = if Vec3::dot(wi, rec.normal) > 0. { 1. } else { -1. };
+            ray = Ray::new(rec.point + rec.normal * (0.00001 * side), wi);
+            from = if sample.specular { None } else { Some(Bounce { point: rec.point, normal: n, pdf: sample.pdf }) };
+
+            if depth + 1 >= RR_START_DEPTH {
+                let p = throughput.x.max(throughput.y).max(throughput.z).min(0.95);
+                if survive >= p {
+                    break;
+                }
+                throughput = throughput / p;
+            }
+        }
+        radiance
+    }
+}
@@ -1,19 +1,641 @@
+use std::f32::consts::PI;
+
 use crate::vec3::*;
+use crate::ray::*;
+use crate::util::*;
+use crate::aabb::*;
+use crate::sampling::*;
+use crate::primitives::*;
+
+/*
+Everything the renderer samples for direct lighting.
+
+SphereLight is the scene file's point light: `intensity` is radiant intensity (color
+included), so irradiance falls off with the square of the distance. The glowing ball has
+radius `radius`; a radius of 0 makes it a point light with hard shadows. Emissive spheres
+become one too.
+
+SpotLight is a point light aimed along `direction` that fades out between the inner and
+outer cones.
+
+TriangleLight and PlaneLight come from emissive objects and glow with `radiance` on both
+sides, the same value the camera sees when it hits them. RectLight and DiskLight glow
+with `radiance` from their front side only and are invisible to the camera, like point and
+spot lights, so only light sampling can find them.
+
+DistantLight is infinitely far away in `direction`, like the sun, delivering `irradiance`
+to a surface facing it. Directions within the cone with cosine `cos_max` are spread evenly and
+rays escaping the scene through it see it, 1 makes it a single direction with hard shadows.
+
+Lights with a place in the scene also describe themselves with LightBounds, which is what
+lets the light sampler guess how much each one matters to a shading point.
+*/
+pub trait Light: Send + Sync {
+    // Picks a direction toward the light as seen from `p` using the two uniform numbers a and b.
+    fn sample_li(&self, p: Vec3, a: f32, b: f32) -> Option<LightSample>;
+
+    // Solid angle density of `sample_li` choosing `wi` from `p`. Lights that shine from a
+    // single point or direction have none, no other direction can find them.
+    fn pdf(&self, p: Vec3, wi: Vec3) -> f32;
+
+    // Whether paths can run into the light, in which case BSDF sampling shares the work.
+    fn is_visible(&self) -> bool {
+        false
+    }
+
+    // Lights at infinity are seen by rays that escape the scene, with radiance `le`.
+    fn is_infinite(&self) -> bool {
+        false
+    }
+
+    fn le(&self, _dir: Vec3) -> Vec3 {
+        Vec3::new(0., 0., 0.)
+    }
+
+    // None for lights with no position, like the sun or an infinite plane.
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+}
+
+// Where a light (or a group of them) is, the power `phi` it puts out as luminance and where
+// that goes: within `cos_theta_o` of `w`, plus up to `cos_theta_e` beyond that edge for
+// surfaces that shine sideways. Two sided lights shine along -`w` as well.
+#[derive(Clone, Copy)]
+pub struct LightBounds {
+    pub bounds: Aabb,
+    pub w: Vec3,
+    pub phi: f32,
+    pub cos_theta_o: f32,
+    pub cos_theta_e: f32,
+    pub two_sided: bool
+}
+
+impl LightBounds {
+    // Point and ball lights shine every way.
+    pub fn omni(bounds: Aabb, phi: f32) -> LightBounds {
+        LightBounds { bounds, w: Vec3::new(0., 0., 1.), phi, cos_theta_o: -1., cos_theta_e: 0., two_sided: false }
+    }
+
+    // Flat emitters facing `normal`, light leaving at up to 90 degrees.
+    pub fn flat(bounds: Aabb, normal: Vec3, phi: f32, two_sided: bool) -> LightBounds {
+        LightBounds { bounds, w: Vec3::unit_vec(normal), phi, cos_theta_o: 1., cos_theta_e: 0., two_sided }
+    }
+
+    pub fn union(self, other: LightBounds) -> LightBounds {
+        if self.phi <= 0. {
+            return other;
+        }
+        if other.phi <= 0. {
+            return self;
+        }
+        let (w, cos_theta_o) = cone_union(self.w, self.cos_theta_o, other.w, other.cos_theta_o);
+        LightBounds {
+            bounds: self.bounds.union(other.bounds),
+            w,
+            phi: self.phi + other.phi,
+            cos_theta_o,
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+            two_sided: self.two_sided || other.two_sided
+        }
+    }
+
+    // Conservative guess at how much light reaches `p` on a surface facing `n`: power over
+    // squared distance, times the best cosines any point in the box could manage both at the
+    // light and at `p`. From Conty Estevez and Kulla, "Importance Sampling of Many Lights with
+    // Adaptive Tree Splitting" (2018), as done in pbrt-v4.
+    pub fn importance(&self, p: Vec3, n: Vec3) -> f32 {
+        let center = self.bounds.centroid();
+        //closer than the box is big, every point in it might be the near one
+        let dist2 = (p - center).length_squared().max(self.bounds.extent().length() / 2.);
+        let wi = Vec3::unit_vec(p - center);
+        let cos_w = Vec3::dot(self.w, wi);
+        let cos_w = if self.two_sided { cos_w.abs() } else { cos_w };
+        let sin_w = safe_sqrt(1. - cos_w * cos_w);
+
+        //cone the box fills as seen from p
+        let radius2 = (self.bounds.max - center).length_squared();
+        let cos_b = if (p - center).length_squared() <= radius2 {
+            -1.
+        } else {
+            safe_sqrt(1. - radius2 / (p - center).length_squared())
+        };
+        let sin_b = safe_sqrt(1. - cos_b * cos_b);
+        let sin_o = safe_sqrt(1. - self.cos_theta_o * self.cos_theta_o);
+
+        //angle from the emission cone to p, less whatever the box's size can take off
+        let (cos_x, sin_x) = if cos_w > self.cos_theta_o {
+            (1., 0.)
+        } else {
+            (cos_w * self.cos_theta_o + sin_w * sin_o, sin_w * self.cos_theta_o - cos_w * sin_o)
+        };
+        let cos_p = if cos_x > cos_b { 1. } else { cos_x * cos_b + sin_x * sin_b };
+        if cos_p <= self.cos_theta_e {
+            return 0.;
+        }
+
+        let cos_i = Vec3::dot(wi, n).abs();
+        let sin_i = safe_sqrt(1. - cos_i * cos_i);
+        let cos_i = if cos_i > cos_b { 1. } else { cos_i * cos_b + sin_i * sin_b };
+        (self.phi * cos_p * cos_i / dist2).max(0.)
+    }
+}
 
+// Smallest cone holding both cones given by an axis and the cosine of their half angle.
+fn cone_union(wa: Vec3, cos_a: f32, wb: Vec3, cos_b: f32) -> (Vec3, f32) {
+    let (theta_a, theta_b) = (cos_a.clamp(-1., 1.).acos(), cos_b.clamp(-1., 1.).acos());
+    let theta_d = Vec3::dot(wa, wb).clamp(-1., 1.).acos();
+    if (theta_d + theta_b).min(PI) <= theta_a {
+        return (wa, cos_a);
+    }
+    if (theta_d + theta_a).min(PI) <= theta_b {
+        return (wb, cos_b);
+    }
+    let theta_o = (theta_a + theta_d + theta_b) / 2.;
+    let axis = Vec3::cross(wa, wb);
+    if theta_o >= PI || axis.length_squared() == 0. {
+        return (Vec3::new(0., 0., 1.), -1.);
+    }
+    //turn wa toward wb until the cone's near edge meets a's
+    let (k, theta_r) = (Vec3::unit_vec(axis), theta_o - theta_a);
+    let w = wa * theta_r.cos() + Vec3::cross(k, wa) * theta_r.sin() + k * (Vec3::dot(k, wa) * (1. - theta_r.cos()));
+    (w, theta_o.cos())
+}
+
+fn safe_sqrt(x: f32) -> f32 {
+    x.max(0.).sqrt()
+}
+
+// A direction toward a sampled point on a light. `weight` is the arriving radiance
+// divided by the solid angle pdf of picking `wi`, ready to be multiplied by the cosine term.
 #[derive(Clone, Copy)]
-pub struct Light {
-    /*
-    BUG: Lighting gets gradually glitchier the higher it is on the y axis
-    Y58 is the highest the light can be without glitches
-     */
+pub struct LightSample {
+    pub wi: Vec3,
+    pub dist: f32,
+    pub weight: Vec3,
+    // Solid angle pdf of `wi`, 0 for lights only sampling can find.
+    pub pdf: f32
+}
+
+pub struct SphereLight {
+    pub position: Vec3,
+    pub intensity: Vec3,
+    pub radius: f32,
+    // Set when the ball is also an object in the scene.
+    pub visible: bool
+}
+
+impl SphereLight {
+    pub fn new(position: Vec3, color: Vec3, intensity: f32, radius: f32) -> SphereLight {
+        SphereLight { position, intensity: color * intensity, radius, visible: false }
+    }
+
+    pub fn with_visible(mut self, visible: bool) -> SphereLight {
+        self.visible = visible;
+        self
+    }
+
+    // Cosine and solid angle of the cone the ball fills as seen from `p`, None from inside it
+    // or from so far away that the cone vanishes.
+    fn cone(&self, p: Vec3) -> Option<(f32, f32)> {
+        let dist2 = (self.position - p).length_squared();
+        let sin2_max = self.radius * self.radius / dist2;
+        if sin2_max >= 1. {
+            return None;
+        }
+        let cos_max = (1. - sin2_max).sqrt();
+        //2 PI (1 - cos_max) without the cancellation, about PI sin2_max for far lights
+        let solid_angle = 2. * PI * sin2_max / (1. + cos_max);
+        if solid_angle > 0. { Some((cos_max, solid_angle)) } else { None }
+    }
+}
+
+impl Light for SphereLight {
+    // Directions are drawn uniformly from the cone the sphere subtends.
+    fn sample_li(&self, p: Vec3, a: f32, b: f32) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let dist2 = to_light.length_squared();
+        let dist = dist2.sqrt();
+        let r2 = self.radius * self.radius;
+
+        if self.radius <= 0. {
+            return Some(LightSample { wi: to_light / dist, dist, weight: self.intensity / dist2, pdf: 0. });
+        }
+        //inside the light, nothing sensible to sample
+        let (cos_max, solid_angle) = self.cone(p)?;
+        let (wi, cos_theta) = sample_cone(to_light / dist, cos_max, a, b);
+        let sin2_theta = 1. - cos_theta * cos_theta;
+
+        //distance to the near side of the sphere along wi
+        let dist_surface = dist * cos_theta - (r2 - dist2 * sin2_theta).max(0.).sqrt();
+
+        let radiance = self.intensity / (PI * r2);
+        Some(LightSample { wi, dist: dist_surface, weight: radiance * solid_angle, pdf: 1. / solid_angle })
+    }
+
+    fn pdf(&self, p: Vec3, wi: Vec3) -> f32 {
+        if self.radius <= 0. {
+            return 0.;
+        }
+        match self.cone(p) {
+            Some((cos_max, solid_angle)) if Vec3::dot(Vec3::unit_vec(wi), Vec3::unit_vec(self.position - p)) >= cos_max => 1. / solid_angle,
+            _ => 0.,
+        }
+    }
+
+    fn is_visible(&self) -> bool {
+        self.visible && self.radius > 0.
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        Some(LightBounds::omni(Aabb::new(self.position - r, self.position + r), 4. * PI * luminance(self.intensity)))
+    }
+}
+
+pub struct SpotLight {
     pub position: Vec3,
-    pub color: Vec3,
-    pub intensity: f32, 
-    pub radius: f32
+    pub direction: Vec3,
+    pub intensity: Vec3,
+    pub cos_inner: f32,
+    pub cos_outer: f32
+}
+
+impl SpotLight {
+    // `inner` and `outer` are the angles in degrees from the axis to where the light starts
+    // fading and where it's gone.
+    pub fn new(position: Vec3, direction: Vec3, color: Vec3, intensity: f32, inner: f32, outer: f32) -> SpotLight {
+        let cos_outer = outer.to_radians().cos();
+        let cos_inner = inner.min(outer).to_radians().cos();
+        SpotLight { position, direction: Vec3::unit_vec(direction), intensity: color * intensity, cos_inner, cos_outer }
+    }
+}
+
+impl Light for SpotLight {
+    fn sample_li(&self, p: Vec3, _a: f32, _b: f32) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let dist2 = to_light.length_squared();
+        let dist = dist2.sqrt();
+        let wi = to_light / dist;
+        let cos = -Vec3::dot(wi, self.direction);
+        let falloff = smoothstep(self.cos_outer, self.cos_inner, cos);
+        if falloff <= 0. {
+            return None;
+        }
+        Some(LightSample { wi, dist, weight: self.intensity * (falloff / dist2), pdf: 0. })
+    }
+
+    fn pdf(&self, _p: Vec3, _wi: Vec3) -> f32 {
+        0.
+    }
+
+    //power as if it shone every way, the cones say where it actually goes
+    fn bounds(&self) -> Option<LightBounds> {
+        let cos_e = (self.cos_outer.clamp(-1., 1.).acos() - self.cos_inner.clamp(-1., 1.).acos()).cos();
+        Some(LightBounds {
+            bounds: Aabb::new(self.position, self.position),
+            w: self.direction,
+            phi: 4. * PI * luminance(self.intensity),
+            cos_theta_o: self.cos_inner,
+            cos_theta_e: cos_e,
+            two_sided: false
+        })
+    }
+}
+
+pub struct TriangleLight {
+    pub v0: Vec3,
+    pub v1: Vec3,
+    pub v2: Vec3,
+    pub radiance: Vec3
+}
+
+impl TriangleLight {
+    pub fn new(v0: Vec3, v1: Vec3, v2: Vec3, radiance: Vec3) -> TriangleLight {
+        TriangleLight { v0, v1, v2, radiance }
+    }
+}
+
+impl Light for TriangleLight {
+    // Uniform point on the triangle's area, converted to a solid angle pdf.
+    fn sample_li(&self, p: Vec3, a: f32, b: f32) -> Option<LightSample> {
+        let su = a.sqrt();
+        let point = self.v0 * (1. - su) + self.v1 * (su * (1. - b)) + self.v2 * (su * b);
+        //half the cross product, as long as the triangle's area
+        let cross = Vec3::cross(self.v1 - self.v0, self.v2 - self.v0) * 0.5;
+        sample_area(point, cross, self.radiance, true, p)
+    }
+
+    fn pdf(&self, p: Vec3, wi: Vec3) -> f32 {
+        match intersect_triangle(self.v0, self.v1, self.v2, &Ray::new(p, wi), 0., f32::INFINITY) {
+            Some((t, _, _)) => area_pdf(Vec3::cross(self.v1 - self.v0, self.v2 - self.v0) * 0.5, wi, t * wi.length(), true),
+            None => 0.,
+        }
+    }
+
+    fn is_visible(&self) -> bool {
+        true
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let cross = Vec3::cross(self.v1 - self.v0, self.v2 - self.v0);
+        let area = cross.length() / 2.;
+        if area <= 0. {
+            return None;
+        }
+        let bounds = Aabb::from_points(&[self.v0, self.v1, self.v2]);
+        Some(LightBounds::flat(bounds, cross, 2. * PI * area * luminance(self.radiance), true))
+    }
+}
+
+pub struct PlaneLight {
+    pub p0: Vec3,
+    pub normal: Vec3,
+    pub radiance: Vec3
+}
+
+impl PlaneLight {
+    pub fn new(p0: Vec3, normal: Vec3, radiance: Vec3) -> PlaneLight {
+        PlaneLight { p0, normal: Vec3::unit_vec(normal), radiance }
+    }
+
+    // Unit normal pointing from `p` at the plane, None on it.
+    fn toward(&self, p: Vec3) -> Option<(Vec3, f32)> {
+        let height = Vec3::dot(p - self.p0, self.normal);
+        if height == 0. {
+            return None;
+        }
+        Some((self.normal * -height.signum(), height.abs()))
+    }
+}
+
+impl Light for PlaneLight {
+    // An infinite plane fills the whole half of the sky facing it, so sample that hemisphere uniformly.
+    fn sample_li(&self, p: Vec3, a: f32, b: f32) -> Option<LightSample> {
+        let (toward, height) = self.toward(p)?;
+        let wi = to_world(uniform_hemisphere(a, b), toward);
+        let cos = Vec3::dot(wi, toward);
+        if cos <= 0. {
+            return None;
+        }
+        Some(LightSample { wi, dist: height / cos, weight: self.radiance * (2. * PI), pdf: 1. / (2. * PI) })
+    }
+
+    fn pdf(&self, p: Vec3, wi: Vec3) -> f32 {
+        match self.toward(p) {
+            Some((toward, _)) if Vec3::dot(wi, toward) > 0. => 1. / (2. * PI),
+            _ => 0.,
+        }
+    }
+
+    fn is_visible(&self) -> bool {
+        true
+    }
+}
+
+pub struct RectLight {
+    pub center: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub radiance: Vec3
+}
+
+impl RectLight {
+    // Spans `center` +- u/2 +- v/2 and shines toward cross(u, v).
+    pub fn new(center: Vec3, u: Vec3, v: Vec3, radiance: Vec3) -> RectLight {
+        RectLight { center, u, v, radiance }
+    }
+}
+
+impl Light for RectLight {
+    fn sample_li(&self, p: Vec3, a: f32, b: f32) -> Option<LightSample> {
+        let point = self.center + self.u * (a - 0.5) + self.v * (b - 0.5);
+        sample_area(point, Vec3::cross(self.u, self.v), self.radiance, false, p)
+    }
+
+    fn pdf(&self, p: Vec3, wi: Vec3) -> f32 {
+        let cross = Vec3::cross(self.u, self.v);
+        let t = match intersect_plane(self.center, cross, p, wi) {
+            Some(t) => t,
+            None => return 0.,
+        };
+        let d = p + wi * t - self.center;
+        let s = Vec3::dot(d, self.u) / self.u.length_squared();
+        let r = Vec3::dot(d, self.v) / self.v.length_squared();
+        if s.abs() > 0.5 || r.abs() > 0.5 {
+            return 0.;
+        }
+        area_pdf(cross, wi, t * wi.length(), false)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let cross = Vec3::cross(self.u, self.v);
+        if cross.length_squared() <= 0. {
+            return None;
+        }
+        let (hu, hv) = (self.u * 0.5, self.v * 0.5);
+        let c = self.center;
+        let bounds = Aabb::from_points(&[c - hu - hv, c + hu - hv, c - hu + hv, c + hu + hv]);
+        Some(LightBounds::flat(bounds, cross, PI * cross.length() * luminance(self.radiance), false))
+    }
+}
+
+pub struct DiskLight {
+    pub center: Vec3,
+    pub normal: Vec3,
+    pub radius: f32,
+    pub radiance: Vec3
+}
+
+impl DiskLight {
+    // Shines toward `normal`.
+    pub fn new(center: Vec3, normal: Vec3, radius: f32, radiance: Vec3) -> DiskLight {
+        DiskLight { center, normal: Vec3::unit_vec(normal), radius, radiance }
+    }
+}
+
+impl Light for DiskLight {
+    fn sample_li(&self, p: Vec3, a: f32, b: f32) -> Option<LightSample> {
+        let (x, y) = concentric_disk(a, b);
+        let point = self.center + to_world(Vec3::new(x * self.radius, y * self.radius, 0.), self.normal);
+        //cross product with the disk's area as its length
+        sample_area(point, self.normal * (PI * self.radius * self.radius), self.radiance, false, p)
+    }
+
+    fn pdf(&self, p: Vec3, wi: Vec3) -> f32 {
+        let t = match intersect_plane(self.center, self.normal, p, wi) {
+            Some(t) => t,
+            None => return 0.,
+        };
+        if (p + wi * t - self.center).length_squared() > self.radius * self.radius {
+            return 0.;
+        }
+        area_pdf(self.normal * (PI * self.radius * self.radius), wi, t * wi.length(), false)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        let area = PI * self.radius * self.radius;
+        Some(LightBounds::flat(Aabb::new(self.center - r, self.center + r), self.normal, PI * area * luminance(self.radiance), false))
+    }
+}
+
+pub struct DistantLight {
+    pub direction: Vec3,
+    pub irradiance: Vec3,
+    pub cos_max: f32
+}
+
+impl DistantLight {
+    pub fn new(direction: Vec3, irradiance: Vec3, cos_max: f32) -> DistantLight {
+        DistantLight { direction: Vec3::unit_vec(direction), irradiance, cos_max }
+    }
+
+    fn solid_angle(&self) -> f32 {
+        2. * PI * (1. - self.cos_max)
+    }
+}
+
+impl Light for DistantLight {
+    // Uniform over the cone. Radiance times solid angle is the irradiance whatever the size.
+    fn sample_li(&self, _p: Vec3, a: f32, b: f32) -> Option<LightSample> {
+        if self.cos_max >= 1. {
+            return Some(LightSample { wi: self.direction, dist: f32::INFINITY, weight: self.irradiance, pdf: 0. });
+        }
+        let (wi, _) = sample_cone(self.direction, self.cos_max, a, b);
+        Some(LightSample { wi, dist: f32::INFINITY, weight: self.irradiance, pdf: 1. / self.solid_angle() })
+    }
+
+    fn pdf(&self, _p: Vec3, wi: Vec3) -> f32 {
+        if self.cos_max >= 1. {
+            return 0.;
+        }
+        cone_pdf(self.direction, self.cos_max, wi)
+    }
+
+    fn is_visible(&self) -> bool {
+        self.cos_max < 1.
+    }
+
+    fn is_infinite(&self) -> bool {
+        true
+    }
+
+    fn le(&self, dir: Vec3) -> Vec3 {
+        if self.cos_max >= 1. || Vec3::dot(Vec3::unit_vec(dir), self.direction) < self.cos_max {
+            return Vec3::new(0., 0., 0.);
+        }
+        self.irradiance / self.solid_angle()
+    }
+}
+
+// Uniform direction within `cos_max` of `axis`, and its cosine to the axis.
+fn sample_cone(axis: Vec3, cos_max: f32, a: f32, b: f32) -> (Vec3, f32) {
+    let cos_theta = 1. - a * (1. - cos_max);
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let phi = 2. * PI * b;
+    (to_world(Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta), axis), cos_theta)
+}
+
+fn cone_pdf(axis: Vec3, cos_max: f32, wi: Vec3) -> f32 {
+    if Vec3::dot(Vec3::unit_vec(wi), axis) < cos_max {
+        return 0.;
+    }
+    1. / (2. * PI * (1. - cos_max))
+}
+
+// Light from `point` on a flat emitter whose normal has the emitter's area as its length.
+// One sided emitters only shine toward `cross`.
+fn sample_area(point: Vec3, cross: Vec3, radiance: Vec3, two_sided: bool, p: Vec3) -> Option<LightSample> {
+    let area = cross.length();
+    let to_light = point - p;
+    let dist2 = to_light.length_squared();
+    let dist = dist2.sqrt();
+    if area <= 0. || dist <= 0. {
+        return None;
+    }
+    let wi = to_light / dist;
+    let cos_light = -Vec3::dot(cross / area, wi);
+    let cos_light = if two_sided { cos_light.abs() } else { cos_light };
+    if cos_light <= 0. {
+        return None;
+    }
+    let pdf = dist2 / (area * cos_light);
+    Some(LightSample { wi, dist, weight: radiance / pdf, pdf })
+}
+
+// Solid angle pdf of `sample_area` reaching the emitter `dist` away along `wi`.
+fn area_pdf(cross: Vec3, wi: Vec3, dist: f32, two_sided: bool) -> f32 {
+    let area = cross.length();
+    let cos_light = -Vec3::dot(cross / area, Vec3::unit_vec(wi));
+    let cos_light = if two_sided { cos_light.abs() } else { cos_light };
+    if area <= 0. || cos_light <= 0. {
+        return 0.;
+    }
+    dist * dist / (area * cos_light)
+}
+
+// Ray parameter where p + t wi crosses the plane through `point`, if ahead.
+fn intersect_plane(point: Vec3, normal: Vec3, p: Vec3, wi: Vec3) -> Option<f32> {
+    let denom = Vec3::dot(normal, wi);
+    if denom == 0. {
+        return None;
+    }
+    let t = Vec3::dot(point - p, normal) / denom;
+    if t > 0. { Some(t) } else { None }
 }
 
-impl Light {
-    pub fn new(position: Vec3, color: Vec3, intensity: f32, radius: f32) -> Light {
-        Light {position, color, intensity, radius}
+fn smoothstep(low: f32, high: f32, x: f32) -> f32 {
+    if high <= low {
+        return if x >= high { 1. } else { 0. };
     }
+    let t = ((x - low) / (high - low)).clamp(0., 1.);
+    t * t * (3. - 2. * t)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Irradiance at `p` facing +Y, from a grid of samples over a light of radiance 1.
+    fn irradiance(light: &dyn Light, p: Vec3) -> f32 {
+        let n = 64;
+        let mut sum = 0.;
+        for i in 0..n {
+            for j in 0..n {
+                let (a, b) = ((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32);
+                if let Some(ls) = light.sample_li(p, a, b) {
+                    assert!((light.pdf(p, ls.wi) * ls.weight.x - 1.).abs() < 1e-3);
+                    sum += ls.weight.x * ls.wi.y.max(0.);
+                }
+            }
+        }
+        sum / (n * n) as f32
+    }
+
+    #[test]
+    fn triangle_pair_matches_rect() {
+        let white = Vec3::new(1., 1., 1.);
+        let (a, b, c, d) = (Vec3::new(-0.5, 1., -0.5), Vec3::new(0.5, 1., -0.5), Vec3::new(0.5, 1., 0.5), Vec3::new(-0.5, 1., 0.5));
+        let rect = RectLight::new(Vec3::new(0., 1., 0.), Vec3::new(1., 0., 0.), Vec3::new(0., 0., 1.), white);
+        let tris = [TriangleLight::new(a, b, c, white), TriangleLight::new(a, c, d, white)];
+
+        for p in [Vec3::new(0., 0., 0.), Vec3::new(0.7, 0.2, -0.3)] {
+            let rect_e = irradiance(&rect, p);
+            let tri_e: f32 = tris.iter().map(|t| irradiance(t, p)).sum();
+            assert!((tri_e / rect_e - 1.).abs() < 0.01, "triangles {} rect {}", tri_e, rect_e);
+        }
+    }
+
+    #[test]
+    fn far_sphere_light_keeps_a_finite_pdf() {
+        let light = SphereLight::new(Vec3::new(0., 0., 0.), Vec3::new(1., 1., 1.), 1., 0.01);
+        let p = Vec3::new(0., 1e5, 0.);
+        let ls = light.sample_li(p, 0.5, 0.5).unwrap();
+        let pdf = light.pdf(p, ls.wi);
+        assert!((pdf / (1e10 / (PI * 1e-4)) - 1.).abs() < 1e-3, "pdf {}", pdf);
+        assert!(ls.weight.x.is_finite() && ls.weight.x > 0.);
+        assert!(light.sample_li(Vec3::new(0., 1e30, 0.), 0.5, 0.5).is_none());
+    }
+}
+
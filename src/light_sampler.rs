@@ -0,0 +1,295 @@
+use std::f32::consts::PI;
+
+use serde::Deserialize;
+
+use crate::vec3::*;
+use crate::aabb::*;
+use crate::light::*;
+use crate::sampling::*;
+
+/*
+Picks the one light `direct_light` samples at a shading point.
+
+Lights without bounds (the environment, the sun, infinite planes) can't be told apart by
+position, so they're picked by count: each is one candidate, and everything with bounds
+together is one more. Among the bounded lights
+
+    uniform  every light is as likely, the old behaviour
+    power    proportional to emitted power, through an alias table
+    bvh      down a tree of LightBounds, at each node the child whose lights are likely to
+             matter more to this point gets picked more often (the default)
+
+Emissive meshes hand over a light per triangle and interiors have dozens of fixtures,
+sampling all of them at every point would cost as much as the lights there are.
+*/
+
+//SAH-like bins per axis when building the light tree
+const BVH_BINS: usize = 12;
+//past this depth nodes split at the median so bit trails fit in a u64
+const MAX_SAH_DEPTH: usize = 40;
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum LightSelection {
+    Uniform,
+    Power,
+    #[default]
+    Bvh
+}
+
+pub struct LightSampler {
+    //lights picked by count
+    infinite: Vec<usize>,
+    //chance of picking from `infinite` at all
+    p_infinite: f32,
+    unbounded: Vec<bool>,
+    strategy: Strategy
+}
+
+enum Strategy {
+    Uniform(Vec<usize>),
+    Power {
+        lights: Vec<usize>,
+        table: AliasTable,
+        //by light index
+        slot: Vec<usize>
+    },
+    Bvh {
+        nodes: Vec<LightNode>,
+        //path from the root to every light, bit i set when it goes right at depth i
+        trails: Vec<u64>
+    }
+}
+
+// Flattened like the object BVH: the left child follows its parent, the right is at `right`.
+#[derive(Clone, Copy)]
+struct LightNode {
+    bounds: LightBounds,
+    light: Option<usize>,
+    right: usize
+}
+
+impl LightSampler {
+    // `bounds` has an entry for every light, None for the unbounded ones.
+    pub fn new(selection: LightSelection, bounds: &[Option<LightBounds>]) -> LightSampler {
+        let infinite: Vec<usize> = (0..bounds.len()).filter(|&i| bounds[i].is_none()).collect();
+        let bounded: Vec<usize> = (0..bounds.len()).filter(|&i| bounds[i].is_some()).collect();
+        let unbounded = bounds.iter().map(|b| b.is_none()).collect();
+
+        let groups = infinite.len() as f32 + match selection {
+            //each bounded light is its own candidate
+            LightSelection::Uniform => bounded.len() as f32,
+            _ => if bounded.is_empty() { 0. } else { 1. },
+        };
+        let p_infinite = if groups > 0. { infinite.len() as f32 / groups } else { 0. };
+
+        let strategy = match selection {
+            LightSelection::Uniform => Strategy::Uniform(bounded),
+            LightSelection::Power => {
+                let table = AliasTable::new(&bounded.iter().map(|&i| bounds[i].unwrap().phi).collect::<Vec<f32>>());
+                let mut slot = vec![0; bounds.len()];
+                for (s, &i) in bounded.iter().enumerate() {
+                    slot[i] = s;
+                }
+                Strategy::Power { lights: bounded, table, slot }
+            }
+            LightSelection::Bvh => {
+                let mut items: Vec<(usize, LightBounds)> = bounded.iter().map(|&i| (i, bounds[i].unwrap())).collect();
+                let mut nodes = vec![];
+                let mut trails = vec![0; bounds.len()];
+                if !items.is_empty() {
+                    build(&mut nodes, &mut trails, &mut items, 0, 0);
+                }
+                Strategy::Bvh { nodes, trails }
+            }
+        };
+        LightSampler { infinite, p_infinite, unbounded, strategy }
+    }
+
+    // Index of a light for the point `p` with shading normal `n`, and the chance it was picked.
+    pub fn sample(&self, p: Vec3, n: Vec3, a: f32) -> Option<(usize, f32)> {
+        if a < self.p_infinite {
+            let count = self.infinite.len();
+            let i = ((a / self.p_infinite * count as f32) as usize).min(count - 1);
+            return Some((self.infinite[i], self.p_infinite / count as f32));
+        }
+        let a = ((a - self.p_infinite) / (1. - self.p_infinite)).min(1. - f32::EPSILON);
+        let p_bounded = 1. - self.p_infinite;
+
+        match &self.strategy {
+            Strategy::Uniform(lights) => {
+                let count = lights.len();
+                if count == 0 {
+                    return None;
+                }
+                let i = ((a * count as f32) as usize).min(count - 1);
+                Some((lights[i], p_bounded / count as f32))
+            }
+            Strategy::Power { lights, table, .. } => {
+                if lights.is_empty() {
+                    return None;
+                }
+                let (i, pmf) = table.sample(a);
+                Some((lights[i], p_bounded * pmf))
+            }
+            Strategy::Bvh { nodes, .. } => {
+                if nodes.is_empty() || nodes[0].bounds.importance(p, n) <= 0. {
+                    return None;
+                }
+                let (mut index, mut a, mut pmf) = (0, a, p_bounded);
+                loop {
+                    let node = &nodes[index];
+                    if let Some(light) = node.light {
+                        return Some((light, pmf));
+                    }
+                    let (left, right) = (index + 1, node.right);
+                    let p_left = split(&nodes[left], &nodes[right], p, n)?;
+                    if a < p_left {
+                        index = left;
+                        a = (a / p_left).min(1. - f32::EPSILON);
+                        pmf *= p_left;
+                    } else {
+                        index = right;
+                        a = ((a - p_left) / (1. - p_left)).min(1. - f32::EPSILON);
+                        pmf *= 1. - p_left;
+                    }
+                }
+            }
+        }
+    }
+
+    // Chance of `sample` picking `light` for the same point and normal.
+    pub fn pmf(&self, p: Vec3, n: Vec3, light: usize) -> f32 {
+        if self.unbounded[light] {
+            return self.p_infinite / self.infinite.len() as f32;
+        }
+        let p_bounded = 1. - self.p_infinite;
+        match &self.strategy {
+            Strategy::Uniform(lights) => p_bounded / lights.len() as f32,
+            Strategy::Power { table, slot, .. } => p_bounded * table.pmf(slot[light]),
+            Strategy::Bvh { nodes, trails } => {
+                if nodes[0].bounds.importance(p, n) <= 0. {
+                    return 0.;
+                }
+                let (mut index, mut trail, mut pmf) = (0, trails[light], p_bounded);
+                while nodes[index].light.is_none() {
+                    let (left, right) = (index + 1, nodes[index].right);
+                    let p_left = match split(&nodes[left], &nodes[right], p, n) {
+                        Some(p_left) => p_left,
+                        None => return 0.,
+                    };
+                    if trail & 1 == 0 {
+                        index = left;
+                        pmf *= p_left;
+                    } else {
+                        index = right;
+                        pmf *= 1. - p_left;
+                    }
+                    trail >>= 1;
+                }
+                pmf
+            }
+        }
+    }
+}
+
+// Chance of going left at a node, None when neither side can light `p`.
+fn split(left: &LightNode, right: &LightNode, p: Vec3, n: Vec3) -> Option<f32> {
+    let (l, r) = (left.bounds.importance(p, n), right.bounds.importance(p, n));
+    if l + r <= 0. {
+        return None;
+    }
+    Some(l / (l + r))
+}
+
+// Builds the subtree for `items`, one light per leaf, and returns its node index.
+fn build(nodes: &mut Vec<LightNode>, trails: &mut [u64], items: &mut [(usize, LightBounds)], trail: u64, depth: usize) -> usize {
+    let index = nodes.len();
+    if items.len() == 1 {
+        let (light, bounds) = items[0];
+        nodes.push(LightNode { bounds, light: Some(light), right: 0 });
+        trails[light] = trail;
+        return index;
+    }
+
+    let bounds = items.iter().skip(1).fold(items[0].1, |b, item| b.union(item.1));
+    let centroid_bounds = items.iter().fold(Aabb::empty(), |b, item| b.grow(item.1.bounds.centroid()));
+    nodes.push(LightNode { bounds, light: None, right: 0 });
+
+    let mid = match find_split(items, bounds.bounds, centroid_bounds) {
+        Some(mid) if depth < MAX_SAH_DEPTH => mid,
+        //all in one spot, or deep enough that only a balanced tree fits the trail
+        _ => {
+            let axis = centroid_bounds.largest_axis();
+            items.sort_by(|a, b| a.1.bounds.centroid()[axis].total_cmp(&b.1.bounds.centroid()[axis]));
+            items.len() / 2
+        }
+    };
+
+    let (left, right) = items.split_at_mut(mid);
+    build(nodes, trails, left, trail, depth + 1);
+    let right = build(nodes, trails, right, trail | (1 << depth), depth + 1);
+    nodes[index].right = right;
+    index
+}
+
+// Binned split minimizing power times orientation spread times area, partitioning `items`
+// in place. None when every centroid is in the same spot.
+fn find_split(items: &mut [(usize, LightBounds)], bounds: Aabb, centroid_bounds: Aabb) -> Option<usize> {
+    let mut best: Option<(usize, usize, f32)> = None;
+    let extent = bounds.extent();
+    let longest = extent.x.max(extent.y).max(extent.z);
+
+    for axis in 0..3 {
+        let lo = centroid_bounds.min[axis];
+        let width = centroid_bounds.max[axis] - lo;
+        if width <= 0. {
+            continue;
+        }
+        let bin_of = |c: f32| (((c - lo) / width * BVH_BINS as f32) as usize).min(BVH_BINS - 1);
+
+        let mut bins: [Option<LightBounds>; BVH_BINS] = [None; BVH_BINS];
+        for item in items.iter() {
+            let b = bin_of(item.1.bounds.centroid()[axis]);
+            bins[b] = Some(bins[b].map_or(item.1, |acc| acc.union(item.1)));
+        }
+
+        //long thin boxes are worse at telling their lights apart along the short axes
+        let kr = if extent[axis] > 0. { longest / extent[axis] } else { 1. };
+        for b in 0..BVH_BINS - 1 {
+            let union = |range: &[Option<LightBounds>]| range.iter().flatten().copied().reduce(|acc, l| acc.union(l));
+            let (left, right) = match (union(&bins[..=b]), union(&bins[b + 1..])) {
+                (Some(l), Some(r)) => (l, r),
+                _ => continue,
+            };
+            let cost = kr * (cost(&left) + cost(&right));
+            if best.is_none_or(|(_, _, c)| cost < c) {
+                best = Some((axis, b, cost));
+            }
+        }
+    }
+
+    let (axis, bin, _) = best?;
+    let lo = centroid_bounds.min[axis];
+    let width = centroid_bounds.max[axis] - lo;
+    let bin_of = |c: f32| (((c - lo) / width * BVH_BINS as f32) as usize).min(BVH_BINS - 1);
+    let mut mid = 0;
+    for i in 0..items.len() {
+        if bin_of(items[i].1.bounds.centroid()[axis]) <= bin {
+            items.swap(i, mid);
+            mid += 1;
+        }
+    }
+    Some(mid)
+}
+
+// Power times the solid angle the emission can reach times area.
+fn cost(b: &LightBounds) -> f32 {
+    let theta_o = b.cos_theta_o.clamp(-1., 1.).acos();
+    let theta_e = b.cos_theta_e.clamp(-1., 1.).acos();
+    let theta_w = (theta_o + theta_e).min(PI);
+    let sin_o = (1. - b.cos_theta_o * b.cos_theta_o).max(0.).sqrt();
+    let m_omega = 2. * PI * (1. - b.cos_theta_o)
+        + PI / 2. * (2. * theta_w * sin_o - (theta_o - 2. * theta_w).cos() - 2. * theta_o * sin_o + b.cos_theta_o);
+    b.phi * m_omega * b.bounds.surface_area()
+}
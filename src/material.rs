@@ -1,22 +1,286 @@
+use std::sync::Arc;
+
 use crate::vec3::*;
+use crate::util::*;
+use crate::texture::*;
+use crate::hittable::*;
+use crate::bsdf::*;
 
 /*
 NOTES:
 
-1. Only spheres support emissive material
-2. Reflectivity goes from 0 -> 1
+1. Everything except glass and bare metal is a principled material, the old color +
+   reflectivity pair becomes base color + metallic on a perfectly smooth surface
+2. Emissive surfaces glow with `emission` on both sides, light the scene and don't scatter
+3. Dielectric and conductor tints multiply what the physics gives (white = measured)
+4. Principled and conductor parameters can come from textures, emission and the dielectric's
+   tint and ior can't
+5. Normal and bump maps only bend the shading normal, silhouettes and shadows keep the real
+   geometry
 
 */
 
+// Disney/glTF style parameters, all 0 to 1 except `ior`, as seen at one point of the surface.
 #[derive(Clone, Copy)]
+pub struct Principled {
+    pub base_color: Vec3,
+    pub metallic: f32,
+    pub roughness: f32,
+    // dielectric reflectance at normal incidence, 0.5 = 4%
+    pub specular: f32,
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    pub sheen: f32,
+    pub transmission: f32,
+    pub ior: f32
+}
+
+impl Default for Principled {
+    fn default() -> Principled {
+        Principled {
+            base_color: Vec3::new(0.8, 0.8, 0.8),
+            metallic: 0.,
+            roughness: 0.5,
+            specular: 0.5,
+            clearcoat: 0.,
+            clearcoat_roughness: 0.03,
+            sheen: 0.,
+            transmission: 0.,
+            ior: 1.5
+        }
+    }
+}
+
+impl Principled {
+    // The original material: diffuse `color` with a mirror of strength `reflectivity`.
+    pub fn diffuse(color: Vec3, reflectivity: f32) -> Principled {
+        Principled { base_color: color, metallic: clamp(reflectivity, 0., 1.), roughness: 0., specular: 0., ..Principled::default() }
+    }
+}
+
+// Principled parameters read from textures.
+#[derive(Clone)]
+pub struct PrincipledMaps {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    pub specular: Arc<dyn Texture>,
+    pub clearcoat: Arc<dyn Texture>,
+    pub clearcoat_roughness: Arc<dyn Texture>,
+    pub sheen: Arc<dyn Texture>,
+    pub transmission: Arc<dyn Texture>,
+    pub ior: f32
+}
+
+impl PrincipledMaps {
+    // The same values everywhere.
+    pub fn new(p: &Principled) -> PrincipledMaps {
+        PrincipledMaps {
+            base_color: constant(p.base_color),
+            metallic: constant_scalar(p.metallic),
+            roughness: constant_scalar(p.roughness),
+            specular: constant_scalar(p.specular),
+            clearcoat: constant_scalar(p.clearcoat),
+            clearcoat_roughness: constant_scalar(p.clearcoat_roughness),
+            sheen: constant_scalar(p.sheen),
+            transmission: constant_scalar(p.transmission),
+            ior: p.ior
+        }
+    }
+
+    pub fn at(&self, u: f32, v: f32, p: Vec3) -> Principled {
+        //textures can stray outside 0 to 1, the BSDF can't
+        let fraction = |t: &Arc<dyn Texture>| clamp(t.scalar(u, v, p), 0., 1.);
+        Principled {
+            base_color: self.base_color.value(u, v, p),
+            metallic: fraction(&self.metallic),
+            roughness: fraction(&self.roughness),
+            specular: fraction(&self.specular),
+            clearcoat: fraction(&self.clearcoat),
+            clearcoat_roughness: fraction(&self.clearcoat_roughness),
+            sheen: fraction(&self.sheen),
+            transmission: fraction(&self.transmission),
+            ior: self.ior
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum Surface {
+    Principled(PrincipledMaps),
+    // Smooth glass or water with index of refraction `ior`. Light travelling inside loses
+    // `absorption` per unit of distance (Beer-Lambert), per channel.
+    Dielectric { ior: f32, absorption: Vec3 },
+    // Metal with complex index of refraction `eta` + i`k` per channel and GGX `roughness`
+    // from 0 (mirror) to 1.
+    Conductor { eta: Vec3, k: Vec3, roughness: Arc<dyn Texture>, tint: Arc<dyn Texture> }
+}
+
+//uv step for the bump map's finite differences
+const BUMP_DELTA: f32 = 0.0005;
+
+#[derive(Clone)]
+pub enum NormalMap {
+    // Tangent space normals stored as colors, (0.5, 0.5, 1) leaves the surface as it is.
+    // The texture should be loaded without sRGB decoding.
+    Tangent(Arc<dyn Texture>),
+    // Height field, `scale` world units per unit of texture value.
+    Bump { height: Arc<dyn Texture>, scale: f32 }
+}
+
+impl NormalMap {
+    // Shading normal at `rec`, facing the same way as rec.normal. `wo` points back along the ray.
+    pub fn perturb(&self, rec: &HitRecord, wo: Vec3) -> Vec3 {
+        //work in the outward frame so both sides agree, then face the ray again
+        let side = if rec.front_face { 1. } else { -1. };
+        let n = rec.shading_normal * side;
+        let (u, v, p) = (rec.u, rec.v, rec.point);
+        let perturbed = match self {
+            NormalMap::Tangent(map) => {
+                let c = map.value(u, v, p) * 2. - Vec3::new(1., 1., 1.);
+                //tangent made perpendicular to the normal, bitangent on dpdv's side
+                let t = rec.dpdu - n * Vec3::dot(n, rec.dpdu);
+                if t.length_squared() < 1e-12 {
+                    return rec.shading_normal;
+                }
+                let t = Vec3::unit_vec(t);
+                let b = Vec3::cross(n, t);
+                let b = if Vec3::dot(b, rec.dpdv) < 0. { b * -1. } else { b };
+                t * c.x + b * c.y + n * c.z
+            }
+            NormalMap::Bump { height, scale } => {
+                let h = height.scalar(u, v, p) * scale;
+                let hu = height.scalar(u + BUMP_DELTA, v, p + rec.dpdu * BUMP_DELTA) * scale;
+                let hv = height.scalar(u, v + BUMP_DELTA, p + rec.dpdv * BUMP_DELTA) * scale;
+                let dpdu = rec.dpdu + n * ((hu - h) / BUMP_DELTA);
+                let dpdv = rec.dpdv + n * ((hv - h) / BUMP_DELTA);
+                let bumped = Vec3::cross(dpdu, dpdv);
+                if Vec3::dot(bumped, n) < 0. { bumped * -1. } else { bumped }
+            }
+        };
+        if perturbed.length_squared() < 1e-12 {
+            return rec.shading_normal;
+        }
+        let ns = Vec3::unit_vec(perturbed) * side;
+        //tilted past the viewer there'd be nothing to shade
+        if Vec3::dot(ns, wo) <= 0. {
+            return rec.shading_normal;
+        }
+        ns
+    }
+}
+
+#[derive(Clone)]
 pub struct Material {
-    pub color: Vec3,
-    pub reflectivity: f32,
-    pub emissivity: f32
+    pub surface: Surface,
+    pub emission: Vec3,
+    pub normal_map: Option<NormalMap>
 }
 
 impl Material {
     pub fn new(color: Vec3, reflectivity: f32, emissivity: f32) -> Material {
-        Material { color, reflectivity, emissivity }
+        Material::principled(PrincipledMaps::new(&Principled::diffuse(color, reflectivity))).with_emission(color * emissivity)
     }
-}
\ No newline at end of file
+
+    pub fn principled(maps: PrincipledMaps) -> Material {
+        Material { surface: Surface::Principled(maps), emission: Vec3::new(0., 0., 0.), normal_map: None }
+    }
+
+    // Clear dielectric whose inside turns white light `tint` after `tint_distance` units.
+    pub fn dielectric(ior: f32, tint: Vec3, tint_distance: f32) -> Material {
+        let absorb = |c: f32| -clamp(c, 1e-6, 1.).ln() / tint_distance;
+        let absorption = Vec3::new(absorb(tint.x), absorb(tint.y), absorb(tint.z));
+        Material { surface: Surface::Dielectric { ior, absorption }, emission: Vec3::new(0., 0., 0.), normal_map: None }
+    }
+
+    pub fn conductor(eta: Vec3, k: Vec3, roughness: Arc<dyn Texture>, tint: Arc<dyn Texture>) -> Material {
+        Material { surface: Surface::Conductor { eta, k, roughness, tint }, emission: Vec3::new(0., 0., 0.), normal_map: None }
+    }
+
+    pub fn with_emission(mut self, emission: Vec3) -> Material {
+        self.emission = emission;
+        self
+    }
+
+    pub fn with_normal_map(mut self, normal_map: NormalMap) -> Material {
+        self.normal_map = Some(normal_map);
+        self
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        self.emission.x > 0. || self.emission.y > 0. || self.emission.z > 0.
+    }
+
+    pub fn emitted(&self) -> Vec3 {
+        self.emission
+    }
+
+    // Per unit distance extinction inside the material, if it has an inside.
+    pub fn absorption(&self) -> Option<Vec3> {
+        match self.surface {
+            Surface::Dielectric { absorption, .. } => Some(absorption),
+            _ => None,
+        }
+    }
+
+    // Scattering at `rec`, in the frame of its shading normal.
+    pub fn bsdf(&self, rec: &HitRecord) -> Box<dyn Bsdf> {
+        let (u, v, p) = (rec.u, rec.v, rec.point);
+        match &self.surface {
+            Surface::Principled(maps) => Box::new(PrincipledBsdf::new(&maps.at(u, v, p), rec.front_face)),
+            Surface::Dielectric { ior, .. } => Box::new(DielectricBsdf::new(*ior, rec.front_face)),
+            Surface::Conductor { eta, k, roughness, tint } => {
+                Box::new(ConductorBsdf::new(*eta, *k, clamp(roughness.scalar(u, v, p), 0., 1.), tint.value(u, v, p)))
+            }
+        }
+    }
+}
+
+// Unpolarized Fresnel reflectance of a smooth dielectric boundary. `eta` is the index on the
+// incident side over the index on the far side. Total internal reflection gives 1.
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let sin2_t = eta * eta * (1. - cos_i * cos_i).max(0.);
+    if sin2_t >= 1. {
+        return 1.;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    let r_s = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_p = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_s * r_s + r_p * r_p) / 2.
+}
+
+// Schlick's approximation to the Fresnel curve starting at `f0`.
+pub fn schlick(f0: Vec3, cos_i: f32) -> Vec3 {
+    let m = (1. - clamp(cos_i, 0., 1.)).powi(5);
+    f0 * (1. - m) + Vec3::new(m, m, m)
+}
+
+// Fresnel reflectance of a metal per channel (exact, unpolarized).
+pub fn fresnel_conductor(cos_i: f32, eta: Vec3, k: Vec3) -> Vec3 {
+    let channel = |eta: f32, k: f32| {
+        let cos2 = cos_i * cos_i;
+        let sin2 = 1. - cos2;
+        let t0 = eta * eta - k * k - sin2;
+        let a2b2 = (t0 * t0 + 4. * eta * eta * k * k).sqrt();
+        let t1 = a2b2 + cos2;
+        let a = (0.5 * (a2b2 + t0)).max(0.).sqrt();
+        let t2 = 2. * cos_i * a;
+        let r_s = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let r_p = r_s * (t3 - t4) / (t3 + t4);
+        (r_s + r_p) / 2.
+    };
+    Vec3::new(channel(eta.x, k.x), channel(eta.y, k.y), channel(eta.z, k.z))
+}
+
+// Measured (eta, k) at roughly 650, 550 and 450 nm for a few common metals.
+pub fn metal_preset(name: &str) -> Option<(Vec3, Vec3)> {
+    match name {
+        "gold" => Some((Vec3::new(0.143, 0.374, 1.442), Vec3::new(3.983, 2.386, 1.603))),
+        "copper" => Some((Vec3::new(0.200, 0.924, 1.102), Vec3::new(3.912, 2.452, 2.142))),
+        "aluminum" => Some((Vec3::new(1.657, 0.880, 0.521), Vec3::new(9.224, 6.270, 4.837))),
+        "silver" => Some((Vec3::new(0.155, 0.117, 0.138), Vec3::new(4.828, 3.122, 2.147))),
+        _ => None,
+    }
+}
@@ -0,0 +1,161 @@
+use std::sync::Arc;
+
+use crate::vec3::*;
+use crate::ray::*;
+use crate::aabb::*;
+use crate::material::*;
+use crate::hittable::*;
+use crate::light::*;
+use crate::primitives::*;
+
+// One triangle of a mesh. Each corner indexes the position/normal/uv arrays separately,
+// the same way OBJ stores them.
+#[derive(Clone, Copy, Debug)]
+pub struct MeshFace {
+    pub positions: [usize; 3],
+    pub normals: Option<[usize; 3]>,
+    pub uvs: Option<[usize; 3]>,
+    pub material: usize
+}
+
+// Shared vertex triangle mesh. The BVH holds one small MeshTriangle per face
+// that points back into this instead of a full Triangle copy.
+pub struct Mesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f32, f32)>,
+    pub faces: Vec<MeshFace>,
+    pub materials: Vec<Material>
+}
+
+impl Mesh {
+    pub fn new(positions: Vec<Vec3>, normals: Vec<Vec3>, uvs: Vec<(f32, f32)>, faces: Vec<MeshFace>, materials: Vec<Material>) -> Mesh {
+        Mesh { positions, normals, uvs, faces, materials }
+    }
+
+    // One hittable per face, ready to go in the world.
+    pub fn triangles(mesh: &Arc<Mesh>) -> Vec<Box<dyn Hittable>> {
+        (0..mesh.faces.len())
+            .map(|index| Box::new(MeshTriangle { mesh: mesh.clone(), index, light: None }) as Box<dyn Hittable>)
+            .collect()
+    }
+
+    // Gives faces that came without vertex normals smoothed ones, averaging the neighbouring
+    // faces within `max_angle` degrees so hard edges stay hard. 0 leaves them flat.
+    pub fn generate_normals(&mut self, max_angle: f32) {
+        if max_angle <= 0. || self.faces.iter().all(|f| f.normals.is_some()) {
+            return;
+        }
+        //unnormalized, so bigger faces count for more
+        let face_normals: Vec<Vec3> = self.faces.iter().map(|f| {
+            let (v0, v1, v2) = self.vertices(f);
+            Vec3::cross(v1 - v0, v2 - v0)
+        }).collect();
+        let mut around: Vec<Vec<usize>> = vec![vec![]; self.positions.len()];
+        for (i, face) in self.faces.iter().enumerate() {
+            for &p in &face.positions {
+                around[p].push(i);
+            }
+        }
+
+        let cos_max = max_angle.min(180.).to_radians().cos();
+        let unit = |n: Vec3| if n.length_squared() > 0. { Vec3::unit_vec(n) } else { n };
+        for (i, face) in self.faces.iter_mut().enumerate() {
+            let own = unit(face_normals[i]);
+            if face.normals.is_some() || own.length_squared() == 0. {
+                continue;
+            }
+            let mut corners = [0; 3];
+            for (corner, &p) in corners.iter_mut().zip(&face.positions) {
+                let mut sum = Vec3::new(0., 0., 0.);
+                for &j in &around[p] {
+                    if Vec3::dot(own, unit(face_normals[j])) >= cos_max {
+                        sum = sum + face_normals[j];
+                    }
+                }
+                self.normals.push(Vec3::unit_vec(sum));
+                *corner = self.normals.len() - 1;
+            }
+            face.normals = Some(corners);
+        }
+    }
+
+    fn vertices(&self, face: &MeshFace) -> (Vec3, Vec3, Vec3) {
+        let [a, b, c] = face.positions;
+        (self.positions[a], self.positions[b], self.positions[c])
+    }
+}
+
+// dp/du and dp/dv of the flat triangle through `p` with texture coordinates `uv`, the tangent
+// frame normal maps are painted in. Falls back to the edges when the uvs are degenerate.
+fn uv_derivatives(p: [Vec3; 3], uv: [(f32, f32); 3]) -> (Vec3, Vec3) {
+    let (du02, dv02) = (uv[0].0 - uv[2].0, uv[0].1 - uv[2].1);
+    let (du12, dv12) = (uv[1].0 - uv[2].0, uv[1].1 - uv[2].1);
+    let (dp02, dp12) = (p[0] - p[2], p[1] - p[2]);
+    let det = du02 * dv12 - dv02 * du12;
+    if det.abs() < 1e-12 {
+        return (p[1] - p[0], p[2] - p[0]);
+    }
+    ((dp02 * dv12 - dp12 * dv02) / det, (dp12 * du02 - dp02 * du12) / det)
+}
+
+pub struct MeshTriangle {
+    pub mesh: Arc<Mesh>,
+    pub index: usize,
+    pub light: Option<usize>
+}
+
+impl Hittable for MeshTriangle {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let face = &self.mesh.faces[self.index];
+        let (v0, v1, v2) = self.mesh.vertices(face);
+        let (t, b1, b2) = intersect_triangle(v0, v1, v2, ray, t_min, t_max)?;
+        let b0 = 1. - b1 - b2;
+
+        //texture coordinates if the mesh has them, barycentrics otherwise
+        let ((u, v), (dpdu, dpdv)) = match face.uvs {
+            Some([a, b, c]) => {
+                let (uvs0, uvs1, uvs2) = (self.mesh.uvs[a], self.mesh.uvs[b], self.mesh.uvs[c]);
+                let uv = (uvs0.0 * b0 + uvs1.0 * b1 + uvs2.0 * b2, uvs0.1 * b0 + uvs1.1 * b1 + uvs2.1 * b2);
+                (uv, uv_derivatives([v0, v1, v2], [uvs0, uvs1, uvs2]))
+            }
+            None => ((b1, b2), (v1 - v0, v2 - v0)),
+        };
+
+        let p_norm = Vec3::unit_vec(Vec3::cross(v1 - v0, v2 - v0));
+        let rec = HitRecord::new(ray, t, p_norm, u, v, &self.mesh.materials[face.material]).with_derivatives(dpdu, dpdv).with_light(self.light);
+
+        //smooth shading from the vertex normals, turned to agree with the winding
+        match face.normals {
+            Some([a, b, c]) => {
+                let normals = &self.mesh.normals;
+                let n = normals[a] * b0 + normals[b] * b1 + normals[c] * b2;
+                if n.length_squared() < 1e-12 {
+                    return Some(rec);
+                }
+                let n = if Vec3::dot(n, p_norm) < 0. { n * -1. } else { n };
+                Some(rec.with_shading_normal(Vec3::unit_vec(n)))
+            }
+            None => Some(rec),
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let (v0, v1, v2) = self.mesh.vertices(&self.mesh.faces[self.index]);
+        Some(Aabb::from_points(&[v0, v1, v2]).padded())
+    }
+
+    fn emitter(&self) -> Option<Box<dyn Light>> {
+        let face = &self.mesh.faces[self.index];
+        let material = &self.mesh.materials[face.material];
+        if material.is_emissive() {
+            let (v0, v1, v2) = self.mesh.vertices(face);
+            return Some(Box::new(TriangleLight::new(v0, v1, v2, material.emitted())));
+        }
+        None
+    }
+
+    fn attach_light(&mut self, index: usize) {
+        self.light = Some(index);
+    }
+}
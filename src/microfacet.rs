@@ -0,0 +1,92 @@
+use std::f32::consts::PI;
+
+use crate::vec3::*;
+
+/*
+Isotropic GGX (Trowbridge-Reitz) microfacet distribution with Smith masking-shadowing.
+
+Everything works in the local shading frame where the normal is +Z. `alpha` is the width of
+the distribution, artists' roughness squared.
+*/
+
+//below this the lobe is too sharp to sample lights through, so it's treated as a perfect mirror
+const SMOOTH_ALPHA: f32 = 1e-3;
+
+pub fn roughness_to_alpha(roughness: f32) -> f32 {
+    (roughness * roughness).max(1e-4)
+}
+
+pub fn is_smooth(alpha: f32) -> bool {
+    alpha < SMOOTH_ALPHA
+}
+
+// Density of microfacet normals `h`.
+pub fn ggx_d(h: Vec3, alpha: f32) -> f32 {
+    if h.z <= 0. {
+        return 0.;
+    }
+    let a2 = alpha * alpha;
+    let d = h.z * h.z * (a2 - 1.) + 1.;
+    a2 / (PI * d * d)
+}
+
+// Fraction of microfacets visible from direction `w`.
+pub fn smith_g1(w: Vec3, alpha: f32) -> f32 {
+    if w.z <= 0. {
+        return 0.;
+    }
+    let tan2 = (1. - w.z * w.z).max(0.) / (w.z * w.z);
+    let lambda = ((1. + alpha * alpha * tan2).sqrt() - 1.) / 2.;
+    1. / (1. + lambda)
+}
+
+// Smith shadowing for the pair, treating the two directions as uncorrelated.
+pub fn smith_g2(wo: Vec3, wi: Vec3, alpha: f32) -> f32 {
+    smith_g1(wo, alpha) * smith_g1(wi, alpha)
+}
+
+// Samples a microfacet normal from the distribution of normals visible from `wo`
+// (Heitz 2018, "Sampling the GGX Distribution of Visible Normals").
+pub fn sample_vndf(wo: Vec3, alpha: f32, a: f32, b: f32) -> Vec3 {
+    //stretch the view so the problem becomes the hemisphere case
+    let vh = Vec3::unit_vec(Vec3::new(alpha * wo.x, alpha * wo.y, wo.z));
+    let len2 = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if len2 > 0. { Vec3::new(-vh.y, vh.x, 0.) / len2.sqrt() } else { Vec3::new(1., 0., 0.) };
+    let t2 = Vec3::cross(vh, t1);
+
+    //uniform disk point, squashed onto the part of the hemisphere that's visible
+    let r = a.sqrt();
+    let phi = 2. * PI * b;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1. + vh.z);
+    let p2 = (1. - s) * (1. - p1 * p1).max(0.).sqrt() + s * r * phi.sin();
+    let nh = t1 * p1 + t2 * p2 + vh * (1. - p1 * p1 - p2 * p2).max(0.).sqrt();
+
+    //unstretch
+    Vec3::unit_vec(Vec3::new(alpha * nh.x, alpha * nh.y, nh.z.max(1e-6)))
+}
+
+// Pdf of `sample_vndf` picking `h`, as seen from `wo`.
+pub fn vndf_pdf(wo: Vec3, h: Vec3, alpha: f32) -> f32 {
+    if wo.z <= 0. {
+        return 0.;
+    }
+    smith_g1(wo, alpha) * Vec3::dot(wo, h).max(0.) * ggx_d(h, alpha) / wo.z
+}
+
+// Mirrors `w` about `h`.
+pub fn reflect(w: Vec3, h: Vec3) -> Vec3 {
+    h * (2. * Vec3::dot(w, h)) - w
+}
+
+// Bends `w` through the boundary with normal `h` on its side. `eta` is the index on w's side
+// over the far one. None on total internal reflection.
+pub fn refract(w: Vec3, h: Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = Vec3::dot(w, h);
+    let sin2_t = eta * eta * (1. - cos_i * cos_i).max(0.);
+    if sin2_t >= 1. {
+        return None;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    Some(w * -eta + h * (eta * cos_i - cos_t))
+}
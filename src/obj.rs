@@ -0,0 +1,428 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::vec3::*;
+use crate::material::*;
+use crate::mesh::*;
+use crate::texture::*;
+
+/*
+Wavefront OBJ/MTL loader.
+
+Supports v, vt, vn, f (any polygon, triangulated on load), usemtl and mtllib.
+Groups, objects and smoothing groups are ignored. Faces before the first usemtl
+(or naming a material we couldn't find) get the default material. Faces without vn
+get normals smoothed across edges sharper than `smooth_angle` degrees (0 = flat).
+
+MTL mapping onto Material:
+  Kd            -> color
+  Ke            -> emission
+  Ks with illum >= 3 (raytraced reflection) -> reflectivity
+  illum 4, 6 or 7 (glass) -> dielectric with ior Ni and tint Tf
+  any of the PBR extension's Pr, Pm, Ps, Pc, Pcr -> principled with base color Kd,
+  roughness Pr, metallic Pm, sheen Ps, clearcoat Pc / Pcr, transmission 1 - d and ior Ni
+  map_Kd, map_Pr, map_Pm -> image textures for the base color, roughness and metallic
+  norm          -> tangent space normal map
+  bump, map_Bump -> height map, -bm sets its scale (default 1)
+  (other map options are skipped, the file name is the last argument)
+*/
+
+// Problems that don't stop the load (a missing MTL file, an unknown material) are pushed to
+// `warnings` for the caller to report.
+pub fn load_obj(path: &Path, default_material: Material, smooth_angle: f32, warnings: &mut Vec<String>) -> Result<Arc<Mesh>, String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let mut positions: Vec<Vec3> = vec![];
+    let mut normals: Vec<Vec3> = vec![];
+    let mut uvs: Vec<(f32, f32)> = vec![];
+    let mut faces: Vec<MeshFace> = vec![];
+    let mut materials: Vec<Material> = vec![default_material];
+    let mut library: HashMap<String, Material> = HashMap::new();
+    let mut material_ids: HashMap<String, usize> = HashMap::new();
+    let mut current_material = 0;
+
+    for (number, line) in source.lines().enumerate() {
+        let err = |msg: String| format!("{}:{}: {}", path.display(), number + 1, msg);
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => positions.push(parse_vec3(&args).map_err(err)?),
+            "vn" => normals.push(parse_vec3(&args).map_err(err)?),
+            "vt" => {
+                let u = parse_float(args.first().copied()).map_err(err)?;
+                let v = match args.get(1) {
+                    Some(_) => parse_float(args.get(1).copied()).map_err(err)?,
+                    None => 0.,
+                };
+                uvs.push((u, v));
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(err(format!("face needs at least 3 vertices, got {}", args.len())));
+                }
+                let mut corners = vec![];
+                for arg in &args {
+                    corners.push(parse_corner(arg, positions.len(), uvs.len(), normals.len()).map_err(err)?);
+                }
+                let points: Vec<Vec3> = corners.iter().map(|c| positions[c.0]).collect();
+                for [a, b, c] in triangulate(&points) {
+                    let (ca, cb, cc) = (corners[a], corners[b], corners[c]);
+                    faces.push(MeshFace {
+                        positions: [ca.0, cb.0, cc.0],
+                        uvs: match (ca.1, cb.1, cc.1) {
+                            (Some(x), Some(y), Some(z)) => Some([x, y, z]),
+                            _ => None,
+                        },
+                        normals: match (ca.2, cb.2, cc.2) {
+                            (Some(x), Some(y), Some(z)) => Some([x, y, z]),
+                            _ => None,
+                        },
+                        material: current_material,
+                    });
+                }
+            }
+            "mtllib" => {
+                for name in &args {
+                    let mtl_path = path.parent().unwrap_or(Path::new("")).join(name);
+                    match load_mtl(&mtl_path) {
+                        Ok(mtl) => library.extend(mtl),
+                        Err(e) => warnings.push(e),
+                    }
+                }
+            }
+            "usemtl" => {
+                let name = args.join(" ");
+                current_material = match material_ids.get(&name) {
+                    Some(&id) => id,
+                    None => match library.get(&name) {
+                        Some(material) => {
+                            materials.push(material.clone());
+                            material_ids.insert(name, materials.len() - 1);
+                            materials.len() - 1
+                        }
+                        None => {
+                            warnings.push(err(format!("unknown material '{}', using default", name)));
+                            0
+                        }
+                    },
+                };
+            }
+            //groups, objects, smoothing groups and anything else we don't care about
+            _ => {}
+        }
+    }
+
+    if faces.is_empty() {
+        return Err(format!("{}: no faces", path.display()));
+    }
+
+    let mut mesh = Mesh::new(positions, normals, uvs, faces, materials);
+    mesh.generate_normals(smooth_angle);
+    Ok(Arc::new(mesh))
+}
+
+pub fn load_mtl(path: &Path) -> Result<HashMap<String, Material>, String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    struct Entry {
+        diffuse: Vec3,
+        specular: Vec3,
+        emission: Vec3,
+        transmission: Vec3,
+        ior: f32,
+        illum: i32,
+        dissolve: f32,
+        pbr: bool,
+        principled: Principled,
+        diffuse_map: Option<Arc<dyn Texture>>,
+        roughness_map: Option<Arc<dyn Texture>>,
+        metallic_map: Option<Arc<dyn Texture>>,
+        normal_map: Option<NormalMap>
+    }
+
+    fn finish(entry: &Entry) -> Material {
+        let material = surface(entry).with_emission(entry.emission);
+        match &entry.normal_map {
+            Some(map) => material.with_normal_map(map.clone()),
+            None => material,
+        }
+    }
+
+    fn surface(entry: &Entry) -> Material {
+        if matches!(entry.illum, 4 | 6 | 7) && !entry.pbr {
+            return Material::dielectric(entry.ior, entry.transmission, 1.);
+        }
+        let principled = if entry.pbr {
+            Principled { base_color: entry.diffuse, transmission: 1. - entry.dissolve, ior: entry.ior, ..entry.principled }
+        } else {
+            let reflectivity = if entry.illum >= 3 { entry.specular.x.max(entry.specular.y).max(entry.specular.z) } else { 0. };
+            Principled::diffuse(entry.diffuse, reflectivity)
+        };
+        let mut maps = PrincipledMaps::new(&principled);
+        if let Some(t) = &entry.diffuse_map {
+            maps.base_color = t.clone();
+        }
+        if let Some(t) = &entry.roughness_map {
+            maps.roughness = t.clone();
+        }
+        if let Some(t) = &entry.metallic_map {
+            maps.metallic = t.clone();
+        }
+        Material::principled(maps)
+    }
+
+    //the same picture is often shared by several materials
+    let mut images: HashMap<(PathBuf, bool), Arc<dyn Texture>> = HashMap::new();
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, Entry)> = None;
+
+    for (number, line) in source.lines().enumerate() {
+        let err = |msg: String| format!("{}:{}: {}", path.display(), number + 1, msg);
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            if let Some((name, entry)) = current.take() {
+                materials.insert(name, finish(&entry));
+            }
+            let entry = Entry { diffuse: Vec3::new(0.8, 0.8, 0.8), specular: Vec3::new(0., 0., 0.), emission: Vec3::new(0., 0., 0.), transmission: Vec3::new(1., 1., 1.), ior: 1.5, illum: 2, dissolve: 1., pbr: false, principled: Principled::default(), diffuse_map: None, roughness_map: None, metallic_map: None, normal_map: None };
+            current = Some((args.join(" "), entry));
+            continue;
+        }
+
+        let entry = match current.as_mut() {
+            Some((_, entry)) => entry,
+            None => return Err(err(format!("'{}' before any newmtl", keyword))),
+        };
+        match keyword {
+            "Kd" => entry.diffuse = parse_vec3(&args).map_err(err)?,
+            "Ks" => entry.specular = parse_vec3(&args).map_err(err)?,
+            "Ke" => entry.emission = parse_vec3(&args).map_err(err)?,
+            "Tf" => entry.transmission = parse_vec3(&args).map_err(err)?,
+            "d" => entry.dissolve = parse_fraction(&args).map_err(err)?,
+            "Tr" => entry.dissolve = 1. - parse_fraction(&args).map_err(err)?,
+            "Pr" | "Pm" | "Ps" | "Pc" | "Pcr" => {
+                let value = parse_fraction(&args).map_err(err)?;
+                let p = &mut entry.principled;
+                match keyword {
+                    "Pr" => p.roughness = value,
+                    "Pm" => p.metallic = value,
+                    "Ps" => p.sheen = value,
+                    "Pc" => p.clearcoat = value,
+                    _ => p.clearcoat_roughness = value,
+                }
+                entry.pbr = true;
+            }
+            "map_Kd" | "map_Pr" | "map_Pm" | "norm" | "bump" | "map_Bump" => {
+                let file = dir.join(args.last().ok_or_else(|| err(format!("{} needs a file", keyword)))?);
+                //colors are stored as sRGB, everything else as it is
+                let srgb = keyword == "map_Kd";
+                let texture = match images.get(&(file.clone(), srgb)) {
+                    Some(t) => t.clone(),
+                    None => {
+                        let t: Arc<dyn Texture> = Arc::new(Image::load(&file, srgb, Wrap::Repeat).map_err(err)?);
+                        images.insert((file, srgb), t.clone());
+                        t
+                    }
+                };
+                match keyword {
+                    "map_Kd" => entry.diffuse_map = Some(texture),
+                    "map_Pr" => entry.roughness_map = Some(texture),
+                    "map_Pm" => entry.metallic_map = Some(texture),
+                    "norm" => entry.normal_map = Some(NormalMap::Tangent(texture)),
+                    _ => {
+                        let scale = match args.iter().position(|&a| a == "-bm") {
+                            Some(i) => parse_float(args.get(i + 1).copied()).map_err(err)?,
+                            None => 1.,
+                        };
+                        entry.normal_map = Some(NormalMap::Bump { height: texture, scale });
+                    }
+                }
+            }
+            "Ni" => entry.ior = args.first().and_then(|s| s.parse().ok()).filter(|&n: &f32| n > 0.).ok_or_else(|| err("bad Ni".to_string()))?,
+            "illum" => entry.illum = args.first().and_then(|s| s.parse().ok()).ok_or_else(|| err("bad illum".to_string()))?,
+            _ => {}
+        }
+    }
+    if let Some((name, entry)) = current.take() {
+        materials.insert(name, finish(&entry));
+    }
+
+    Ok(materials)
+}
+
+fn parse_float(token: Option<&str>) -> Result<f32, String> {
+    let token = token.ok_or("missing number")?;
+    token.parse().map_err(|_| format!("bad number '{}'", token))
+}
+
+fn parse_fraction(args: &[&str]) -> Result<f32, String> {
+    let x = parse_float(args.first().copied())?;
+    if !(0. ..=1.).contains(&x) {
+        return Err(format!("expected a number between 0 and 1, got {}", x));
+    }
+    Ok(x)
+}
+
+fn parse_vec3(args: &[&str]) -> Result<Vec3, String> {
+    if args.len() < 3 {
+        return Err(format!("expected 3 numbers, got {}", args.len()));
+    }
+    Ok(Vec3::new(parse_float(Some(args[0]))?, parse_float(Some(args[1]))?, parse_float(Some(args[2]))?))
+}
+
+// One face corner "v", "v/vt", "v//vn" or "v/vt/vn", resolved to 0-based indices.
+// Negative indices count back from the most recent element.
+fn parse_corner(token: &str, n_positions: usize, n_uvs: usize, n_normals: usize) -> Result<(usize, Option<usize>, Option<usize>), String> {
+    let resolve = |s: &str, count: usize, what: &str| -> Result<usize, String> {
+        let i: i64 = s.parse().map_err(|_| format!("bad {} index '{}'", what, s))?;
+        let index = if i < 0 { count as i64 + i } else { i - 1 };
+        if index < 0 || index >= count as i64 {
+            return Err(format!("{} index {} out of range (have {})", what, i, count));
+        }
+        Ok(index as usize)
+    };
+
+    let mut parts = token.split('/');
+    let position = resolve(parts.next().unwrap_or(""), n_positions, "vertex")?;
+    let uv = match parts.next() {
+        Some(s) if !s.is_empty() => Some(resolve(s, n_uvs, "texture")?),
+        _ => None,
+    };
+    let normal = match parts.next() {
+        Some(s) if !s.is_empty() => Some(resolve(s, n_normals, "normal")?),
+        _ => None,
+    };
+    Ok((position, uv, normal))
+}
+
+// Ear clipping in the polygon's dominant plane. Handles concave polygons; if it ever
+// gets stuck (self intersecting input) the rest is fanned.
+fn triangulate(points: &[Vec3]) -> Vec<[usize; 3]> {
+    let n = points.len();
+    if n == 3 {
+        return vec![[0, 1, 2]];
+    }
+
+    //Newell's method for the polygon normal, then drop its largest axis
+    let mut normal = Vec3::new(0., 0., 0.);
+    for i in 0..n {
+        let (a, b) = (points[i], points[(i + 1) % n]);
+        normal = normal + Vec3::new((a.y - b.y) * (a.z + b.z), (a.z - b.z) * (a.x + b.x), (a.x - b.x) * (a.y + b.y));
+    }
+    let (ax, ay) = if normal.x.abs() > normal.y.abs() && normal.x.abs() > normal.z.abs() {
+        (1, 2)
+    } else if normal.y.abs() > normal.z.abs() {
+        (2, 0)
+    } else {
+        (0, 1)
+    };
+    let flip = normal[3 - ax - ay] < 0.;
+    let p2: Vec<(f32, f32)> = points.iter().map(|p| if flip { (p[ay], p[ax]) } else { (p[ax], p[ay]) }).collect();
+
+    let cross = |o: (f32, f32), a: (f32, f32), b: (f32, f32)| (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0);
+
+    let mut remaining: Vec<usize> = (0..n).collect();
+    let mut triangles = vec![];
+    while remaining.len() > 3 {
+        let m = remaining.len();
+        let ear = (0..m).find(|&i| {
+            let (a, b, c) = (remaining[(i + m - 1) % m], remaining[i], remaining[(i + 1) % m]);
+            if cross(p2[a], p2[b], p2[c]) <= 0. {
+                return false;
+            }
+            //no other vertex may sit inside the ear
+            remaining.iter().all(|&j| {
+                j == a || j == b || j == c
+                    || cross(p2[a], p2[b], p2[j]) < 0. || cross(p2[b], p2[c], p2[j]) < 0. || cross(p2[c], p2[a], p2[j]) < 0.
+            })
+        });
+        match ear {
+            Some(i) => {
+                triangles.push([remaining[(i + m - 1) % m], remaining[i], remaining[(i + 1) % m]]);
+                remaining.remove(i);
+            }
+            None => break,
+        }
+    }
+    for i in 1..remaining.len() - 1 {
+        triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+    }
+    triangles
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    // Writes `source` next to the other test files under `name`.
+    fn file(name: &str, source: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("k-tracer-{}-{}", std::process::id(), name));
+        fs::write(&path, source).unwrap();
+        path
+    }
+
+    fn load(name: &str, source: &str) -> (Arc<Mesh>, Vec<String>) {
+        let path = file(name, source);
+        let mut warnings = vec![];
+        let mesh = load_obj(&path, Material::new(Vec3::new(0.8, 0.8, 0.8), 0., 0.), 60., &mut warnings);
+        fs::remove_file(&path).unwrap();
+        (mesh.unwrap(), warnings)
+    }
+
+    fn area(mesh: &Mesh) -> f32 {
+        mesh.faces.iter().map(|f| {
+            let [a, b, c] = f.positions.map(|i| mesh.positions[i]);
+            Vec3::cross(b - a, c - a).length() / 2.
+        }).sum()
+    }
+
+    #[test]
+    fn quad_becomes_two_triangles() {
+        let (mesh, _) = load("quad.obj", "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n");
+        assert_eq!(mesh.faces.len(), 2);
+        assert!((area(&mesh) - 1.).abs() < 1e-6);
+        let mut used: Vec<usize> = mesh.faces.iter().flat_map(|f| f.positions).collect();
+        used.sort();
+        used.dedup();
+        assert_eq!(used, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn negative_indices_count_back_from_the_last_vertex() {
+        let (positive, _) = load("positive.obj", "v 0 0 0\nv 2 0 0\nv 0 2 0\nvt 0 0\nvt 1 0\nvt 0 1\nf 1/1 2/2 3/3\n");
+        let (negative, _) = load("negative.obj", "v 0 0 0\nv 2 0 0\nv 0 2 0\nvt 0 0\nvt 1 0\nvt 0 1\nf -3/-3 -2/-2 -1/-1\n");
+        assert_eq!(negative.faces[0].positions, positive.faces[0].positions);
+        assert_eq!(negative.faces[0].uvs, Some([0, 1, 2]));
+    }
+
+    #[test]
+    fn materials_come_from_the_mtl_and_unknown_ones_warn() {
+        let mtl = file("materials.mtl", "newmtl red\nKd 1 0 0\n");
+        let (mesh, warnings) = load("materials.obj", &format!(
+            "mtllib {}\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl red\nf 1 2 3\nusemtl nothing\nf 3 2 1\n",
+            mtl.file_name().unwrap().to_str().unwrap()));
+        fs::remove_file(&mtl).unwrap();
+        assert_eq!(mesh.materials.len(), 2);
+        assert_eq!(mesh.faces.iter().map(|f| f.material).collect::<Vec<_>>(), vec![1, 0]);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].ends_with(":7: unknown material 'nothing', using default"), "{}", warnings[0]);
+    }
+}
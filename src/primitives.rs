@@ -1,133 +1,208 @@
+use std::f32::consts::PI;
+
 use crate::vec3::*;
 use crate::ray::*;
+use crate::light::*;
 use crate::material::*;
+use crate::hittable::*;
+use crate::aabb::*;
+use crate::sampling::*;
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct Plane {
-    pub p0: Vec3, 
+    pub p0: Vec3,
     pub normal: Vec3,
-    pub material: Material
+    pub material: Material,
+    pub light: Option<usize>
 }
 
 impl Plane {
     pub fn new(p0: Vec3, normal: Vec3, material: Material) -> Plane {
-        Plane{p0, normal, material}
+        Plane{p0, normal, material, light: None}
     }
+}
 
-    pub fn hit(self, ray: &Ray) -> f32{
+impl Hittable for Plane {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
 
         //almost zero (hehe haha)
-        let aZero:f32 = 0.00000001;
-
-        let normal = self.normal;
-        let denom = Vec3::dot(normal, ray.direction());
-        if denom > aZero {
-            let v = self.p0 - ray.origin();
-            let dist = Vec3::dot(v, normal) / denom;
-            if dist > 0. {
-                return dist;
-            }
+        let a_zero:f32 = 0.00000001;
+
+        //`normal` points away from the front side, both sides are solid
+        let denom = Vec3::dot(self.normal, ray.direction());
+        if denom.abs() <= a_zero {
+            return None;
+        }
+        let t = Vec3::dot(self.p0 - ray.origin(), self.normal) / denom;
+        if t <= t_min || t >= t_max {
+            return None;
         }
-        return -1.; 
+        //uv are world units along a basis lying in the plane, so textures tile forever
+        let (tangent, bitangent) = orthonormal_basis(Vec3::unit_vec(self.normal));
+        let d = ray.at(t) - self.p0;
+        Some(HitRecord::new(ray, t, Vec3::unit_vec(self.normal * -1.), Vec3::dot(d, tangent), Vec3::dot(d, bitangent), &self.material)
+            .with_derivatives(tangent, bitangent).with_light(self.light))
     }
 
-    pub fn t(self, ray: &Ray) -> f32 {
-        return self.hit(&ray);
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+
+    fn emitter(&self) -> Option<Box<dyn Light>> {
+        if self.material.is_emissive() {
+            return Some(Box::new(PlaneLight::new(self.p0, self.normal, self.material.emitted())));
+        }
+        None
+    }
+
+    fn attach_light(&mut self, index: usize) {
+        self.light = Some(index);
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct Triangle {
     pub v0: Vec3,
-    pub v1: Vec3, 
+    pub v1: Vec3,
     pub v2: Vec3,
-    pub material: Material
+    pub material: Material,
+    pub light: Option<usize>
 }
 
 impl Triangle {
     pub fn new(v0: Vec3, v1: Vec3, v2: Vec3, material: Material) -> Triangle {
-        Triangle {v0, v1, v2, material}
+        Triangle {v0, v1, v2, material, light: None}
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let (t, u, v) = intersect_triangle(self.v0, self.v1, self.v2, ray, t_min, t_max)?;
+        let p_norm = Vec3::unit_vec(Vec3::cross(self.v1 - self.v0, self.v2 - self.v0));
+        //(u, v) are the barycentrics, so the edges are the derivatives
+        Some(HitRecord::new(ray, t, p_norm, u, v, &self.material).with_derivatives(self.v1 - self.v0, self.v2 - self.v0).with_light(self.light))
     }
 
-    pub fn hit(self, ray: &Ray) -> Vec<Vec4> {
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::from_points(&[self.v0, self.v1, self.v2]).padded())
+    }
 
-        //almost zero (hehe haha)
-        let aZero:f32 = 0.00000001;
-
-        //compute un-normalized normal
-        let orig = ray.origin();
-        let dir = ray.direction();
-        let v0v1 = self.v1 - self.v0;
-        let v0v2 = self.v2 - self.v0;
-        let pvec = Vec3::cross(dir, v0v2);
-        let det = Vec3::dot(v0v1, pvec);
-
-        if det.abs() < aZero {
-            return vec![Vec4::new(0.,0.,0.,0.)]}
-
-        let invDet = 1. / det;
-
-        let tvec = orig - self.v0;
-        let u = Vec3::dot(tvec, pvec) * invDet;
-        if u < 0. || u > 1. {
-            return vec![Vec4::new(0.,0.,0.,0.)]}
-        
-        let qvec = Vec3::cross(tvec, v0v1);
-        let v = Vec3::dot(dir, qvec) * invDet;
-        if v < 0. || u + v > 1. {
-            return vec![Vec4::new(0.,0.,0.,0.)]}
-        
-        let t = Vec3::dot(v0v2, qvec) * invDet;
-        
-        let pNorm = Vec3::unit_vec(Vec3::cross(self.v1-self.v0, self.v2-self.v0));
-
-        return vec![Vec4::new(u, v, 1., t), Vec4::new(pNorm.x, pNorm.y, pNorm.z, 0.)];
-
-    }
-
-    pub fn t(self, ray: &Ray) -> f32 {
-        let v0v1 = self.v1 - self.v0;
-        let v0v2 = self.v2 - self.v0;
-        let pvec = Vec3::cross(ray.direction(), v0v2);
-        let det = Vec3::dot(v0v1, pvec);
-        if det.abs() < 0.00000001 {return 0.}
-        let tvec = ray.origin() - self.v0;
-        let u = Vec3::dot(tvec, pvec) / det;
-        if u < 0. || u > 1. {return 0.;}
-        let qvec = Vec3::cross(tvec, v0v1);
-        let v = Vec3::dot(ray.direction(), qvec) / det;
-        if v < 0. || u + v > 1. {return 0.;}
-        return Vec3::dot(v0v2, qvec) / det;
+    fn emitter(&self) -> Option<Box<dyn Light>> {
+        if self.material.is_emissive() {
+            return Some(Box::new(TriangleLight::new(self.v0, self.v1, self.v2, self.material.emitted())));
+        }
+        None
+    }
+
+    fn attach_light(&mut self, index: usize) {
+        self.light = Some(index);
+    }
+}
+
+// Moller-Trumbore. Returns t and the barycentric u, v of the hit.
+pub fn intersect_triangle(v0: Vec3, v1: Vec3, v2: Vec3, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32, f32)> {
+
+    //almost zero (hehe haha)
+    let a_zero:f32 = 0.00000001;
+
+    let orig = ray.origin();
+    let dir = ray.direction();
+    let v0v1 = v1 - v0;
+    let v0v2 = v2 - v0;
+    let pvec = Vec3::cross(dir, v0v2);
+    let det = Vec3::dot(v0v1, pvec);
+
+    if det.abs() < a_zero {
+        return None;
+    }
+
+    let inv_det = 1. / det;
+
+    let tvec = orig - v0;
+    let u = Vec3::dot(tvec, pvec) * inv_det;
+    if !(0. ..=1.).contains(&u) {
+        return None;
     }
+
+    let qvec = Vec3::cross(tvec, v0v1);
+    let v = Vec3::dot(dir, qvec) * inv_det;
+    if v < 0. || u + v > 1. {
+        return None;
+    }
+
+    let t = Vec3::dot(v0v2, qvec) * inv_det;
+    if t <= t_min || t >= t_max {
+        return None;
+    }
+    Some((t, u, v))
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct Sphere {
-    pub center: Vec3, 
+    pub center: Vec3,
     pub radius: f32,
-    pub material: Material
+    pub material: Material,
+    pub light: Option<usize>
 }
 
 impl Sphere {
     pub fn new(center: Vec3, radius: f32, material: Material) -> Sphere {
-        Sphere {center, radius, material}
+        Sphere {center, radius, material, light: None}
     }
+}
 
-    pub fn hit(self, ray: &Ray) -> f32 {
+impl Hittable for Sphere {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
         let oc: Vec3 = ray.origin() - self.center;
-        let a= Vec3::dot(ray.direction(), ray.direction());
-        let b= 2.0 * Vec3::dot(oc, ray.direction());
-        let c= Vec3::dot(oc, oc) - self.radius * self.radius;
-        let disc= b*b - 4.*a*c;
+        let a = Vec3::dot(ray.direction(), ray.direction());
+        let half_b = Vec3::dot(oc, ray.direction());
+        let c = Vec3::dot(oc, oc) - self.radius * self.radius;
+        let disc = half_b * half_b - a * c;
 
         if disc < 0. {
-            return -1.0;
-        } else {
-            return (-b - disc.sqrt()) / (2.0 * a);
+            return None;
+        }
+
+        //nearest root in range, falling back to the far one when we start inside
+        let sqrtd = disc.sqrt();
+        let mut t = (-half_b - sqrtd) / a;
+        if t <= t_min || t >= t_max {
+            t = (-half_b + sqrtd) / a;
+            if t <= t_min || t >= t_max {
+                return None;
+            }
+        }
+
+        let outward_normal = (ray.at(t) - self.center) / self.radius;
+        let u = ((-outward_normal.z).atan2(outward_normal.x) + PI) / (2. * PI);
+        let v = (-outward_normal.y).acos() / PI;
+        let rec = HitRecord::new(ray, t, outward_normal, u, v, &self.material).with_light(self.light);
+
+        //u runs around the y axis and v from the bottom pole to the top one
+        let d = rec.point - self.center;
+        let rho = (d.x * d.x + d.z * d.z).sqrt();
+        if rho < 1e-6 {
+            return Some(rec);
         }
+        let dpdu = Vec3::new(d.z, 0., -d.x) * (2. * PI);
+        let dpdv = Vec3::new(-d.y * d.x / rho, rho, -d.y * d.z / rho) * PI;
+        Some(rec.with_derivatives(dpdu, dpdv))
     }
 
-    pub fn t(self, ray: &Ray) -> f32 {
-        return self.hit(&ray);
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        Some(Aabb::new(self.center - r, self.center + r))
     }
-}
\ No newline at end of file
+
+    fn emitter(&self) -> Option<Box<dyn Light>> {
+        if self.material.is_emissive() {
+            //same radiance as the surface the camera sees
+            return Some(Box::new(SphereLight::new(self.center, self.material.emitted(), PI * self.radius * self.radius, self.radius).with_visible(true)));
+        }
+        None
+    }
+
+    fn attach_light(&mut self, index: usize) {
+        self.light = Some(index);
+    }
+}
@@ -0,0 +1,239 @@
+use rayon::prelude::*;
+
+use crate::vec3::*;
+use crate::scene::*;
+use crate::sampler::*;
+use crate::framebuffer::*;
+use crate::integrator::*;
+use crate::filter::*;
+
+const TILE_SIZE: u32 = 16;
+
+#[derive(Clone, Copy)]
+struct Tile {
+    x0: u32,
+    y0: u32,
+    x1: u32,
+    y1: u32
+}
+
+// Renders the scene into a framebuffer of filtered linear colors. Tiles are handed out to
+// a rayon pool. Every tile gets its own sampler and every pixel's samples are derived from the
+// render seed and its position. Samples near a tile's edge also land on the pixels of the tiles
+// around it, so each tile splats into its own buffer reaching past its edges by the filter
+// radius, and the buffers are summed in tile order once all are done. That way the image
+// only depends on the seed and never on thread scheduling.
+pub fn render(scene: &Scene, threads: usize, seed: u64) -> Framebuffer {
+    let w = scene.settings.width;
+    let h = scene.settings.height;
+
+    let mut tiles = vec![];
+    for y0 in (0..h).step_by(TILE_SIZE as usize) {
+        for x0 in (0..w).step_by(TILE_SIZE as usize) {
+            tiles.push(Tile { x0, y0, x1: (x0 + TILE_SIZE).min(w), y1: (y0 + TILE_SIZE).min(h) });
+        }
+    }
+
+    //0 threads means one per core
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().expect("failed to start render threads");
+    let splats: Vec<Splats> = pool.install(|| tiles.par_iter().map(|tile| render_tile(scene, tile, seed)).collect());
+
+    let mut image = Splats::new(0, 0, w, h);
+    for tile in &splats {
+        image.merge(tile);
+    }
+    image.resolve()
+}
+
+fn render_tile(scene: &Scene, tile: &Tile, seed: u64) -> Splats {
+    let w = scene.settings.width;
+    let h = scene.settings.height;
+    let samples = scene.settings.samples;
+    let filter = scene.settings.filter.as_ref();
+    let integrator = PathTracer::new(scene.settings.max_depth);
+    let mut sampler = create_sampler(scene.settings.sampler, samples, seed);
+
+    //pixels this tile's samples can reach
+    let reach = (filter.radius() - 0.5).max(0.).ceil() as u32;
+    let mut splats = Splats::new(tile.x0.saturating_sub(reach), tile.y0.saturating_sub(reach), (tile.x1 + reach).min(w), (tile.y1 + reach).min(h));
+
+    for y in tile.y0..tile.y1 {
+        for x in tile.x0..tile.x1 {
+            for s in 0..samples {
+                sampler.start_pixel_sample(x, y, s);
+                //image position, y down like the rows
+                let (dx, dy) = sampler.get_2d();
+                let (fx, fy) = (x as f32 + dx, y as f32 + dy);
+
+                let r = scene.camera.get_ray(fx / w as f32, 1. - fy / h as f32, sampler.get_2d());
+
+                splats.add(fx, fy, integrator.li(&scene.world, &r, sampler.as_mut()), filter);
+            }
+        }
+    }
+    splats
+}
+
+// Filter weighted sums of samples for the pixels [x0, x1) x [y0, y1).
+struct Splats {
+    x0: u32,
+    y0: u32,
+    x1: u32,
+    y1: u32,
+    color: Vec<Vec3>,
+    weight: Vec<f32>
+}
+
+impl Splats {
+    fn new(x0: u32, y0: u32, x1: u32, y1: u32) -> Splats {
+        let n = ((x1 - x0) * (y1 - y0)) as usize;
+        Splats { x0, y0, x1, y1, color: vec![Vec3::new(0., 0., 0.); n], weight: vec![0.; n] }
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        ((y - self.y0) * (self.x1 - self.x0) + (x - self.x0)) as usize
+    }
+
+    // Adds a sample at image position (fx, fy) to every pixel whose center the filter reaches.
+    fn add(&mut self, fx: f32, fy: f32, color: Vec3, filter: &dyn Filter) {
+        let r = filter.radius();
+        let range = |f: f32, lo: u32, hi: u32| ((f - 0.5 - r).ceil().max(lo as f32) as u32, ((f - 0.5 + r).floor() + 1.).clamp(lo as f32, hi as f32) as u32);
+        let (px0, px1) = range(fx, self.x0, self.x1);
+        let (py0, py1) = range(fy, self.y0, self.y1);
+        for py in py0..py1 {
+            for px in px0..px1 {
+                let weight = filter.evaluate(px as f32 + 0.5 - fx, py as f32 + 0.5 - fy);
+                if weight != 0. {
+                    let i = self.index(px, py);
+                    self.color[i] = self.color[i] + color * weight;
+                    self.weight[i] += weight;
+                }
+            }
+        }
+    }
+
+    fn merge(&mut self, other: &Splats) {
+        for y in other.y0..other.y1 {
+            for x in other.x0..other.x1 {
+                let (i, j) = (self.index(x, y), other.index(x, y));
+                self.color[i] = self.color[i] + other.color[j];
+                self.weight[i] += other.weight[j];
+            }
+        }
+    }
+
+    // Weighted average per pixel. Negative lobes can leave a pixel with no weight at all.
+    fn resolve(&self) -> Framebuffer {
+        let mut fb = Framebuffer::new(self.x1 - self.x0, self.y1 - self.y0);
+        for y in self.y0..self.y1 {
+            for x in self.x0..self.x1 {
+                let i = self.index(x, y);
+                let weight = self.weight[i];
+                fb.set(x - self.x0, y - self.y0, if weight > 0. { self.color[i] / weight } else { Vec3::new(0., 0., 0.) });
+            }
+        }
+        fb
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn scene(name: &str, source: &str) -> Scene {
+        let path: PathBuf = std::env::temp_dir().join(format!("k-tracer-{}-{}.toml", name, std::process::id()));
+        fs::write(&path, source).unwrap();
+        let scene = load_scene(&path);
+        fs::remove_file(&path).unwrap();
+        scene.unwrap()
+    }
+
+    // A few tiles' worth of diffuse and reflective surfaces lit by a soft light, with a filter
+    // wide enough for samples to cross tile edges.
+    fn tiny_scene(name: &str) -> Scene {
+        scene(name, r#"
+            [render]
+            width = 40
+            height = 24
+            samples = 4
+            filter = "gaussian"
+
+            [material.floor]
+            color = [0.8, 0.8, 0.8]
+
+            [material.ball]
+            color = [0.9, 0.6, 0.2]
+            reflectivity = 0.5
+
+            [[plane]]
+            point = [0, -0.5, 0]
+            normal = [0, 1, 0]
+            material = "floor"
+
+            [[sphere]]
+            center = [0, 0, -2]
+            radius = 0.5
+            material = "ball"
+
+            [[light]]
+            position = [1, 1, -1]
+            radius = 0.2
+        "#)
+    }
+
+    fn bits(fb: &Framebuffer) -> Vec<[u32; 3]> {
+        fb.pixels.iter().map(|p| [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()]).collect()
+    }
+
+    #[test]
+    fn same_image_on_any_thread_count() {
+        let scene = tiny_scene("threads");
+        assert_eq!(bits(&render(&scene, 1, 7)), bits(&render(&scene, 4, 7)));
+    }
+
+    #[test]
+    fn seed_reproduces_image() {
+        let scene = tiny_scene("seed");
+        let first = bits(&render(&scene, 0, 7));
+        assert_eq!(first, bits(&render(&scene, 0, 7)));
+        assert_ne!(first, bits(&render(&scene, 0, 8)));
+    }
+
+    #[test]
+    fn grazing_floor_stays_finite() {
+        //rays just under the horizon shade the floor millions of units away, where the small
+        //light's solid angle pdf gets huge
+        let scene = scene("grazing", r#"
+            [render]
+            width = 64
+            height = 32
+            samples = 4
+
+            [camera]
+            look_from = [0, 10000, 0]
+            look_at = [0, 10000, -1]
+
+            [material.floor]
+            color = [0.8, 0.8, 0.8]
+
+            [material.glow]
+            color = [1, 1, 1]
+            emissivity = 5.0
+
+            [[plane]]
+            point = [0, 0, 0]
+            normal = [0, 1, 0]
+            material = "floor"
+
+            [[triangle]]
+            vertices = [[-0.1, 1, -3], [0.1, 1, -3], [0, 1, -2.9]]
+            material = "glow"
+        "#);
+        for seed in 0..4 {
+            assert!(render(&scene, 0, seed).pixels.iter().all(|p| p.x.is_finite() && p.y.is_finite() && p.z.is_finite()));
+        }
+    }
+}
@@ -0,0 +1,39 @@
+/*
+PCG32 (O'Neill, "PCG: A Family of Simple Fast Space-Efficient Statistically Good Algorithms
+for Random Number Generation", 2014), XSH RR output on a 64 bit LCG.
+
+Written out here rather than taken from a crate so the numbers are fixed by this file and no
+dependency update can change them: the same seed reproduces the image on the same build and
+machine. Each odd `inc` picks an independent stream, which is how every sample of every pixel
+gets its own.
+*/
+#[derive(Clone, Copy)]
+pub struct Pcg32 {
+    state: u64,
+    inc: u64
+}
+
+const PCG_MULTIPLIER: u64 = 6364136223846793005;
+
+impl Pcg32 {
+    pub fn new(seed: u64, stream: u64) -> Pcg32 {
+        let mut rng = Pcg32 { state: 0, inc: (stream << 1) | 1 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(PCG_MULTIPLIER).wrapping_add(self.inc);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    // Uniform in [0, 1), 24 random bits so every value is exact.
+    pub fn uniform(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+}
@@ -0,0 +1,395 @@
+use serde::Deserialize;
+
+use crate::util::*;
+use crate::rng::*;
+
+/*
+Where every random decision of a path gets its numbers from: the position inside the pixel,
+the point on the lens, then at every bounce the light pick, the point on the light, the
+BSDF lobe and direction, and russian roulette. Each decision asks for the next dimension
+in that fixed order, so sample i of a pixel lines up across dimensions and a
+low-discrepancy sequence can spread the pixel's samples evenly in each of them.
+
+    independent  plain random numbers
+    stratified   each dimension split into as many strata as there are samples, one sample
+                 per stratum in a random order (2D splits into a grid, square sample counts
+                 work best)
+    halton       radical inverses in a different prime base per dimension, Owen scrambled
+    sobol        Owen scrambled Sobol points, two dimensions at a time, each pair with its
+                 own shuffle of the sample order (the default)
+
+Everything is derived from the seed, the pixel and the sample index: the random numbers come
+from a PCG stream of their own per pixel sample and the scrambles from hashes, so an image
+only depends on the seed, bit for bit, and not on threads or tiles.
+*/
+pub trait Sampler {
+    // Moves to sample `index` of pixel (x, y) and back to the first dimension.
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32);
+
+    fn get_1d(&mut self) -> f32;
+
+    fn get_2d(&mut self) -> (f32, f32);
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    #[default]
+    Sobol
+}
+
+// A sampler for `samples` samples per pixel.
+pub fn create_sampler(kind: SamplerKind, samples: u32, seed: u64) -> Box<dyn Sampler> {
+    match kind {
+        SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+        SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples, seed)),
+        SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+        SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+    }
+}
+
+//largest f32 below 1
+const ONE_MINUS_EPSILON: f32 = 1. - f32::EPSILON / 2.;
+
+// Hash of the seed and the pixel, the root of everything a pixel's samples use.
+fn pixel_hash(seed: u64, x: u32, y: u32) -> u64 {
+    mix_seed(mix_seed(seed, x as u64), y as u64)
+}
+
+pub struct IndependentSampler {
+    seed: u64,
+    rng: Pcg32
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> IndependentSampler {
+        IndependentSampler { seed, rng: Pcg32::new(seed, 0) }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.rng = Pcg32::new(pixel_hash(self.seed, x, y), index as u64);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.rng.uniform()
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.rng.uniform(), self.rng.uniform())
+    }
+}
+
+pub struct StratifiedSampler {
+    samples: u32,
+    //grid for 2D strata, x_strata * y_strata = samples
+    x_strata: u32,
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dimension: u64,
+    rng: Pcg32
+}
+
+impl StratifiedSampler {
+    pub fn new(samples: u32, seed: u64) -> StratifiedSampler {
+        //the most square grid that uses every sample
+        let samples = samples.max(1);
+        let mut x_strata = ((samples as f32).sqrt() as u32).max(1);
+        while !samples.is_multiple_of(x_strata) {
+            x_strata -= 1;
+        }
+        StratifiedSampler { samples, x_strata, seed, pixel: seed, index: 0, dimension: 0, rng: Pcg32::new(seed, 0) }
+    }
+
+    // This sample's stratum in the current dimension, a different shuffle per dimension.
+    fn stratum(&mut self) -> u32 {
+        self.dimension += 1;
+        permutation_element(self.index % self.samples, self.samples, mix_seed(self.pixel, self.dimension) as u32)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel = pixel_hash(self.seed, x, y);
+        self.index = index;
+        self.dimension = 0;
+        self.rng = Pcg32::new(self.pixel, index as u64);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let stratum = self.stratum();
+        ((stratum as f32 + self.rng.uniform()) / self.samples as f32).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let stratum = self.stratum();
+        let (x_strata, y_strata) = (self.x_strata, self.samples / self.x_strata);
+        let (sx, sy) = (stratum % x_strata, stratum / x_strata);
+        let x = (sx as f32 + self.rng.uniform()) / x_strata as f32;
+        let y = (sy as f32 + self.rng.uniform()) / y_strata as f32;
+        (x.min(ONE_MINUS_EPSILON), y.min(ONE_MINUS_EPSILON))
+    }
+}
+
+//dimensions past this many primes fall back to scrambled base 2
+const HALTON_DIMENSIONS: usize = 256;
+
+pub struct HaltonSampler {
+    primes: Vec<u64>,
+    seed: u64,
+    pixel: u64,
+    index: u64,
+    dimension: usize
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> HaltonSampler {
+        let mut primes: Vec<u64> = Vec::with_capacity(HALTON_DIMENSIONS);
+        let mut n = 2;
+        while primes.len() < HALTON_DIMENSIONS {
+            if primes.iter().take_while(|&&p| p * p <= n).all(|&p| n % p != 0) {
+                primes.push(n);
+            }
+            n += 1;
+        }
+        HaltonSampler { primes, seed, pixel: seed, index: 0, dimension: 0 }
+    }
+
+    fn next(&mut self) -> f32 {
+        let dim = self.dimension;
+        self.dimension += 1;
+        let base = if dim < self.primes.len() { self.primes[dim] } else { 2 };
+        owen_scrambled_radical_inverse(base, self.index, mix_seed(self.pixel, dim as u64))
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel = pixel_hash(self.seed, x, y);
+        self.index = index as u64;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.next()
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.next(), self.next())
+    }
+}
+
+pub struct SobolSampler {
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dimension: u64
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> SobolSampler {
+        SobolSampler { seed, pixel: seed, index: 0, dimension: 0 }
+    }
+
+    // Both dimensions of the next pair. Burley, "Practical Hash-based Owen Scrambling" (2020):
+    // the first two Sobol dimensions are a (0, 2) sequence, and shuffling the sample order
+    // differently for every pair keeps pairs from correlating with each other.
+    fn next_pair(&mut self) -> (f32, f32) {
+        let seed = mix_seed(self.pixel, self.dimension) as u32;
+        self.dimension += 1;
+        let index = nested_uniform_scramble(self.index, seed);
+        let x = nested_uniform_scramble(sobol(index, &SOBOL_0), mix_seed(seed as u64, 0) as u32);
+        let y = nested_uniform_scramble(sobol(index, &SOBOL_1), mix_seed(seed as u64, 1) as u32);
+        (to_unit(x), to_unit(y))
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel = pixel_hash(self.seed, x, y);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.next_pair().0
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        self.next_pair()
+    }
+}
+
+//generator matrices of the first two Sobol dimensions: bit reversal, and x + 1 as the
+//primitive polynomial with m_1 = 1 (Joe and Kuo's table)
+const SOBOL_0: [u32; 32] = sobol_directions(false);
+const SOBOL_1: [u32; 32] = sobol_directions(true);
+
+const fn sobol_directions(second: bool) -> [u32; 32] {
+    let mut v = [0u32; 32];
+    let mut m: u32 = 1;
+    let mut k = 0;
+    while k < 32 {
+        v[k] = if second { m << (31 - k) } else { 1 << (31 - k) };
+        //m_k = 2 m_(k-1) xor m_(k-1)
+        m = (m << 1) ^ m;
+        k += 1;
+    }
+    v
+}
+
+fn sobol(index: u32, directions: &[u32; 32]) -> u32 {
+    let mut x = 0;
+    let mut bits = index;
+    let mut k = 0;
+    while bits != 0 {
+        if bits & 1 != 0 {
+            x ^= directions[k];
+        }
+        bits >>= 1;
+        k += 1;
+    }
+    x
+}
+
+// Laine and Karras' hash, which only lets bits affect the ones above them. Run on reversed
+// bits that makes it an Owen scramble.
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x.reverse_bits()
+}
+
+fn to_unit(x: u32) -> f32 {
+    ((x >> 8) as f32 / (1u32 << 24) as f32).min(ONE_MINUS_EPSILON)
+}
+
+// Digits of `index` mirrored around the radix point, each digit permuted depending on the
+// digits before it, for as many digits as an f32 can hold.
+fn owen_scrambled_radical_inverse(base: u64, index: u64, hash: u64) -> f32 {
+    let inv_base = 1. / base as f32;
+    let (mut a, mut reversed, mut inv_base_m) = (index, 0u64, 1f32);
+    while 1. - (base - 1) as f32 * inv_base_m < 1. {
+        let next = a / base;
+        let digit = (a - next * base) as u32;
+        let digit_hash = mix_seed(hash, reversed) as u32;
+        let digit = permutation_element(digit, base as u32, digit_hash) as u64;
+        reversed = reversed * base + digit;
+        inv_base_m *= inv_base;
+        a = next;
+    }
+    (inv_base_m * reversed as f32).min(ONE_MINUS_EPSILON)
+}
+
+// Element i of a random permutation of 0..n picked by `p`, without storing it. Kensler,
+// "Correlated Multi-Jittered Sampling" (2013).
+fn permutation_element(i: u32, n: u32, p: u32) -> u32 {
+    let mut w = n.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    let mut i = i;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            break;
+        }
+    }
+    i.wrapping_add(p) % n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 4] = [SamplerKind::Independent, SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol];
+
+    // `samples` samples of one pixel, `dims` 2D dimensions each.
+    fn pairs(sampler: &mut dyn Sampler, samples: u32, dims: usize) -> Vec<Vec<(f32, f32)>> {
+        let mut out = vec![vec![]; dims];
+        for s in 0..samples {
+            sampler.start_pixel_sample(3, 7, s);
+            for dim in out.iter_mut() {
+                dim.push(sampler.get_2d());
+            }
+        }
+        out
+    }
+
+    // Whether every cell of every `2^k x 2^(m-k)` grid holds exactly one of the `2^m` points.
+    fn is_net(points: &[(f32, f32)], m: u32) -> bool {
+        (0..=m).all(|k| {
+            let (nx, ny) = (1 << k, 1 << (m - k));
+            let mut cells = vec![0; 1 << m];
+            for &(x, y) in points {
+                cells[(y * ny as f32) as usize * nx + (x * nx as f32) as usize] += 1;
+            }
+            cells.iter().all(|&c| c == 1)
+        })
+    }
+
+    #[test]
+    fn values_in_unit_interval() {
+        for kind in KINDS {
+            let mut sampler = create_sampler(kind, 16, 5);
+            for s in 0..64 {
+                sampler.start_pixel_sample(s % 7, s / 7, s);
+                for _ in 0..40 {
+                    let v = sampler.get_1d();
+                    let (x, y) = sampler.get_2d();
+                    assert!([v, x, y].iter().all(|v| (0. ..1.).contains(v)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stratified_fills_every_stratum() {
+        let mut sampler = StratifiedSampler::new(16, 5);
+        for dim in pairs(&mut sampler, 16, 8) {
+            let mut cells = vec![0; 16];
+            for (x, y) in dim {
+                cells[(y * 4.) as usize * 4 + (x * 4.) as usize] += 1;
+            }
+            assert!(cells.iter().all(|&c| c == 1), "{:?}", cells);
+        }
+    }
+
+    #[test]
+    fn sobol_pairs_are_nets() {
+        let mut sampler = SobolSampler::new(5);
+        for m in [2, 4, 6] {
+            for dim in pairs(&mut sampler, 1 << m, 8) {
+                assert!(is_net(&dim, m), "{} points", 1 << m);
+            }
+        }
+    }
+}
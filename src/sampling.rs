@@ -0,0 +1,214 @@
+use std::f32::consts::PI;
+
+use crate::vec3::*;
+
+// Warps from uniform [0, 1) numbers to the distributions the renderer samples.
+
+// Shirley-Chiu mapping from the unit square to the unit disk, keeps strata intact.
+pub fn concentric_disk(a: f32, b: f32) -> (f32, f32) {
+    let (x, y) = (2. * a - 1., 2. * b - 1.);
+    if x == 0. && y == 0. {
+        return (0., 0.);
+    }
+    let (r, theta) = if x.abs() > y.abs() {
+        (x, PI / 4. * (y / x))
+    } else {
+        (y, PI / 2. - PI / 4. * (x / y))
+    };
+    (r * theta.cos(), r * theta.sin())
+}
+
+// Direction around +Z with pdf cos(theta) / PI.
+pub fn cosine_hemisphere(a: f32, b: f32) -> Vec3 {
+    let (x, y) = concentric_disk(a, b);
+    let z = (1. - x * x - y * y).max(0.).sqrt();
+    Vec3::new(x, y, z)
+}
+
+// Direction around +Z with pdf 1 / (2 PI).
+pub fn uniform_hemisphere(a: f32, b: f32) -> Vec3 {
+    let z = a;
+    let r = (1. - z * z).max(0.).sqrt();
+    let phi = 2. * PI * b;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+// Tangent and bitangent completing a right handed basis with unit vector `n`
+// (Duff et al. 2017, no branches on near parallel axes).
+pub fn orthonormal_basis(n: Vec3) -> (Vec3, Vec3) {
+    let sign = 1f32.copysign(n.z);
+    let a = -1. / (sign + n.z);
+    let b = n.x * n.y * a;
+    (Vec3::new(1. + sign * n.x * n.x * a, sign * b, -sign * n.x),
+     Vec3::new(b, sign + n.y * n.y * a, -n.y))
+}
+
+// Takes a direction expressed in the (t, b, n) frame into world space.
+pub fn to_world(local: Vec3, n: Vec3) -> Vec3 {
+    let (t, b) = orthonormal_basis(n);
+    t * local.x + b * local.y + n * local.z
+}
+
+// The inverse of `to_world`.
+pub fn to_local(v: Vec3, n: Vec3) -> Vec3 {
+    let (t, b) = orthonormal_basis(n);
+    Vec3::new(Vec3::dot(v, t), Vec3::dot(v, b), Vec3::dot(v, n))
+}
+
+// Veach's power heuristic (beta = 2): how much of a sample drawn with density `f` to keep when
+// another strategy with density `g` could have drawn it too. Sharper than the balance
+// heuristic f / (f + g) when one strategy is much better.
+pub fn power_heuristic(f: f32, g: f32) -> f32 {
+    //as a ratio of the two, squaring a huge pdf would overflow
+    if f >= g {
+        if f <= 0. { 0. } else { 1. / (1. + (g / f) * (g / f)) }
+    } else {
+        let r = f / g;
+        r * r / (1. + r * r)
+    }
+}
+
+// Piecewise constant density over [0, 1) with one bucket per entry of `func`.
+pub struct Distribution1D {
+    pub func: Vec<f32>,
+    cdf: Vec<f32>,
+    // Average of `func`, the normalization.
+    pub integral: f32
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f32>) -> Distribution1D {
+        let n = func.len();
+        let mut cdf = vec![0.; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i].max(0.) / n as f32;
+        }
+        let integral = cdf[n];
+        //all zero, fall back to uniform
+        for (i, c) in cdf.iter_mut().enumerate().skip(1) {
+            *c = if integral > 0. { *c / integral } else { i as f32 / n as f32 };
+        }
+        Distribution1D { func, cdf, integral }
+    }
+
+    // Continuous position in [0, 1), its pdf and the bucket it fell in.
+    pub fn sample(&self, a: f32) -> (f32, f32, usize) {
+        let n = self.func.len();
+        //last bucket whose cdf is <= a
+        let i = (self.cdf.partition_point(|&c| c <= a) - 1).min(n - 1);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let offset = if width > 0. { (a - self.cdf[i]) / width } else { 0. };
+        let x = ((i as f32 + offset) / n as f32).min(1. - f32::EPSILON);
+        (x, self.pdf_bucket(i), i)
+    }
+
+    pub fn pdf(&self, x: f32) -> f32 {
+        let n = self.func.len();
+        self.pdf_bucket(((x * n as f32) as usize).min(n - 1))
+    }
+
+    fn pdf_bucket(&self, i: usize) -> f32 {
+        if self.integral > 0. { self.func[i].max(0.) / self.integral } else { 1. }
+    }
+}
+
+// Piecewise constant density over the unit square from a row major `width` x `height` grid:
+// a row is picked from the marginal distribution, then a column inside it.
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f32], width: usize, height: usize) -> Distribution2D {
+        let rows: Vec<Distribution1D> = func.chunks(width).take(height).map(|row| Distribution1D::new(row.to_vec())).collect();
+        let marginal = Distribution1D::new(rows.iter().map(|r| r.integral).collect());
+        Distribution2D { rows, marginal }
+    }
+
+    // Point (x, y) in the unit square and its pdf, y indexing rows.
+    pub fn sample(&self, a: f32, b: f32) -> ((f32, f32), f32) {
+        let (y, pdf_y, row) = self.marginal.sample(b);
+        let (x, pdf_x, _) = self.rows[row].sample(a);
+        ((x, y), pdf_x * pdf_y)
+    }
+
+    pub fn pdf(&self, x: f32, y: f32) -> f32 {
+        let n = self.rows.len();
+        let row = ((y * n as f32) as usize).min(n - 1);
+        self.marginal.pdf(y) * self.rows[row].pdf(x)
+    }
+}
+
+// Walker's alias method: picks index i with probability weights[i] / sum in constant time,
+// each bin holding its own share and the index that tops it up.
+pub struct AliasTable {
+    bins: Vec<AliasBin>
+}
+
+#[derive(Clone, Copy)]
+struct AliasBin {
+    //chance of keeping this bin's index once it's picked
+    q: f32,
+    pmf: f32,
+    alias: usize
+}
+
+impl AliasTable {
+    // Negative weights count as 0, all zero falls back to uniform.
+    pub fn new(weights: &[f32]) -> AliasTable {
+        let n = weights.len();
+        let sum: f64 = weights.iter().map(|&w| w.max(0.) as f64).sum();
+        let pmf = |w: f32| if sum > 0. { (w.max(0.) as f64 / sum) as f32 } else { 1. / n as f32 };
+        let mut bins: Vec<AliasBin> = weights.iter().map(|&w| AliasBin { q: 0., pmf: pmf(w), alias: 0 }).collect();
+
+        //Vose: pair every bin below the average with one above it
+        let mut scaled: Vec<f64> = bins.iter().map(|b| b.pmf as f64 * n as f64).collect();
+        let (mut under, mut over): (Vec<usize>, Vec<usize>) = (0..n).partition(|&i| scaled[i] < 1.);
+        while let (Some(&u), Some(&o)) = (under.last(), over.last()) {
+            under.pop();
+            bins[u].q = scaled[u] as f32;
+            bins[u].alias = o;
+            scaled[o] -= 1. - scaled[u];
+            if scaled[o] < 1. {
+                over.pop();
+                under.push(o);
+            }
+        }
+        //whatever is left is 1 up to rounding
+        for i in under.into_iter().chain(over) {
+            bins[i].q = 1.;
+            bins[i].alias = i;
+        }
+        AliasTable { bins }
+    }
+
+    // Index and its probability.
+    pub fn sample(&self, a: f32) -> (usize, f32) {
+        let n = self.bins.len();
+        let offset = ((a * n as f32) as usize).min(n - 1);
+        let up = (a * n as f32 - offset as f32).min(1. - f32::EPSILON);
+        let bin = &self.bins[offset];
+        let i = if up < bin.q { offset } else { bin.alias };
+        (i, self.bins[i].pmf)
+    }
+
+    pub fn pmf(&self, i: usize) -> f32 {
+        self.bins[i].pmf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn power_heuristic_handles_extreme_pdfs() {
+        assert_eq!(power_heuristic(1e20, 1.), 1.);
+        assert!(power_heuristic(1., 1e20) < 1e-30);
+        assert!((power_heuristic(3e30, 3e30) - 0.5).abs() < 1e-6);
+        assert!((power_heuristic(1e-30, 2e-30) - 0.2).abs() < 1e-6);
+        assert_eq!(power_heuristic(f32::INFINITY, 1.), 1.);
+        assert_eq!(power_heuristic(0., 0.), 0.);
+    }
+}
@@ -0,0 +1,848 @@
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::vec3::*;
+use crate::world::*;
+use crate::camera::*;
+use crate::light::*;
+use crate::material::*;
+use crate::hittable::*;
+use crate::primitives::*;
+use crate::mesh::*;
+use crate::obj::*;
+use crate::texture::*;
+use crate::environment::*;
+use crate::sky::*;
+use crate::light_sampler::*;
+use crate::sampler::*;
+use crate::filter::*;
+
+/*
+Scene files are TOML. Everything except the objects themselves is optional:
+
+    [render]
+    width = 640
+    height = 360
+    samples = 30
+    max_depth = 8      # path length cap
+    threads = 0        # 0 = one per core
+    seed = 0           # same seed, same image, whatever the thread count
+    output = "out.png" # .png, .exr, .pfm or .ppm, relative to the working directory
+    sampler = "sobol"  # "sobol", "halton", "stratified" or "independent" random numbers
+    filter = "box"     # pixel filter: "box", "tent", "gaussian", "mitchell" or "lanczos"
+    filter_radius = 0.5 # in pixels, defaults to 0.5 for box, 1 tent, 1.5 gaussian, 2 mitchell
+                       # and 3 lanczos
+    light_sampler = "bvh" # which light to sample at each point: "bvh" (nearby and bright ones
+                       # first), "power" (bright ones first) or "uniform"
+
+    [camera]
+    look_from = [0, 0, 0]
+    look_at = [0, 0, -1]
+    vup = [0, 1, 0]
+    vfov = 90.0        # vertical field of view in degrees
+    aperture = 0.0     # lens diameter, 0 = pinhole
+    focus_dist = 1.0   # distance to the sharp plane, defaults to |look_at - look_from|
+    blades = 0         # aperture blades for polygonal bokeh, 0 = round
+    blade_rotation = 0 # degrees
+
+    [environment]
+    kind = "gradient"  # "gradient" (the default sky), "map" or "sky"
+    intensity = 1.0    # map and sky: radiance scale
+    file = "studio.hdr" # map: equirectangular HDR, EXR or LDR image, relative to the scene file
+    rotation = 0.0     # map: degrees around +y
+    sun_elevation = 45.0 # sky: degrees above the horizon, 0 to 90
+    sun_azimuth = 0.0  # sky: degrees around +y, 0 = toward -z, 90 = toward +x
+    turbidity = 3.0    # sky: haze from 1.7 (very clear) to 10
+    sun_size = 0.53    # sky: angular diameter in degrees, 0 = hard shadows
+
+    [texture.tiles]
+    kind = "checker"   # "image", "checker", "noise" or "constant"
+    even = [1, 1, 1]
+    odd = [0, 0, 0]
+    scale = 1.0        # squares per unit of uv, or per world unit when solid
+    solid = false
+
+    [texture.wood]
+    kind = "image"
+    file = "wood.jpg"  # PNG, JPEG, HDR or EXR, relative to the scene file
+    srgb = true        # false for data like roughness maps, ignored by HDR and EXR
+    wrap = "repeat"    # "repeat", "clamp" or "mirror"
+
+    [texture.marble]
+    kind = "noise"     # Perlin noise in world space
+    low = [0, 0, 0]
+    high = [1, 1, 1]
+    scale = 1.0        # features per world unit
+    octaves = 1        # more adds finer detail
+
+    [texture.grey]
+    kind = "constant"
+    color = [0.5, 0.5, 0.5]
+
+    [material.floor]
+    kind = "diffuse"   # "diffuse" (the default), "principled", "dielectric" or "conductor"
+    color = [0.5, 0.5, 0.5] # colors and 0 to 1 parameters can also name a texture, color = "tiles"
+    reflectivity = 0.0 # diffuse only: strength of a mirror tinted by color
+    emissivity = 0.0   # any kind: > 0 makes the object glow and light the scene
+    emission = [1, 1, 1] # glow color, defaults to color (required when color is a texture)
+    normal_map = "bricks_normal" # any kind: tangent space normal map texture (give it srgb = false)
+    bump = "marble"    # any kind, instead of normal_map: height texture
+    bump_scale = 0.01  # world units of height per unit of bump texture
+
+    [material.plastic]
+    kind = "principled"
+    color = [0.8, 0.1, 0.1] # base color
+    metallic = 0.0     # everything from here on goes from 0 to 1
+    roughness = 0.5
+    specular = 0.5     # 0.5 = 4% reflectance, like most plastics
+    clearcoat = 0.0
+    clearcoat_roughness = 0.03
+    sheen = 0.0
+    transmission = 0.0
+    ior = 1.5          # for transmission
+
+    [material.glass]
+    kind = "dielectric"
+    color = [0.9, 0.95, 1.0] # what white light looks like after tint_distance units inside, no textures
+    ior = 1.5
+    tint_distance = 1.0
+
+    [material.brass]
+    kind = "conductor"
+    metal = "gold"     # gold, copper, aluminum or silver, or give eta = [r, g, b] and k = [r, g, b]
+    roughness = 0.3    # 0 = mirror
+    color = [1, 1, 1]  # tint on top of the measured reflectance
+
+    [[plane]]     point, normal, material
+    [[sphere]]    center, radius, material
+    [[triangle]]  vertices = [[x, y, z], [x, y, z], [x, y, z]], material
+    [[mesh]]      file (OBJ, relative to the scene file), material (used where the OBJ has none),
+                  smooth_angle (degrees, edges sharper than this stay faceted where the OBJ has
+                  no normals, default 60, 0 = flat)
+    [[light]]     kind = "point" (the default), "spot", "directional", "rect" or "disk", color, intensity
+                  point: position, intensity (falls off with distance squared), radius (0 = hard shadows)
+                  spot: position, direction it points, inner_angle and outer_angle (degrees from the
+                        axis where it starts fading and where it's gone, default 30 and 45)
+                  directional: direction the light travels, intensity (irradiance), angle (angular
+                        diameter in degrees, 0 = hard shadows)
+                  rect: position (center), u and v (edge vectors, shines toward u x v), intensity (radiance)
+                  disk: position (center), direction it faces, radius, intensity (radiance)
+
+Errors come back as "file:line: message".
+*/
+
+#[derive(Clone)]
+pub struct RenderSettings {
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32,
+    pub max_depth: u32,
+    pub threads: usize,
+    pub seed: u64,
+    pub output: Option<String>,
+    pub sampler: SamplerKind,
+    pub filter: Arc<dyn Filter>
+}
+
+pub struct Scene {
+    pub world: World,
+    pub camera: Camera,
+    pub settings: RenderSettings,
+    // Things in the scene's files that were skipped or replaced rather than failing the load.
+    pub warnings: Vec<String>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    #[serde(default = "unspanned")]
+    render: Spanned<RenderDesc>,
+    #[serde(default)]
+    camera: CameraDesc,
+    #[serde(default = "unspanned")]
+    environment: Spanned<EnvironmentDesc>,
+    #[serde(default)]
+    texture: HashMap<String, Spanned<TextureDesc>>,
+    #[serde(default)]
+    material: HashMap<String, Spanned<MaterialDesc>>,
+    #[serde(default)]
+    plane: Vec<PlaneDesc>,
+    #[serde(default)]
+    sphere: Vec<SphereDesc>,
+    #[serde(default)]
+    triangle: Vec<TriangleDesc>,
+    #[serde(default)]
+    mesh: Vec<MeshDesc>,
+    #[serde(default)]
+    light: Vec<Spanned<LightDesc>>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, default)]
+struct RenderDesc {
+    width: u32,
+    height: u32,
+    samples: u32,
+    max_depth: u32,
+    threads: usize,
+    seed: u64,
+    output: Option<String>,
+    sampler: SamplerKind,
+    filter: FilterKind,
+    filter_radius: Option<Spanned<f32>>,
+    light_sampler: LightSelection
+}
+
+impl Default for RenderDesc {
+    fn default() -> RenderDesc {
+        RenderDesc { width: 640, height: 360, samples: 30, max_depth: 8, threads: 0, seed: 0, output: None, sampler: SamplerKind::Sobol, filter: FilterKind::Box, filter_radius: None, light_sampler: LightSelection::Bvh }
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum FilterKind {
+    #[default]
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    Lanczos
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, default)]
+struct CameraDesc {
+    look_from: [f32; 3],
+    look_at: Spanned<[f32; 3]>,
+    vup: Spanned<[f32; 3]>,
+    vfov: Spanned<f32>,
+    aperture: Spanned<f32>,
+    focus_dist: Option<Spanned<f32>>,
+    blades: Spanned<u32>,
+    blade_rotation: f32
+}
+
+impl Default for CameraDesc {
+    fn default() -> CameraDesc {
+        CameraDesc {
+            look_from: [0., 0., 0.],
+            look_at: Spanned::new(0..0, [0., 0., -1.]),
+            vup: Spanned::new(0..0, [0., 1., 0.]),
+            vfov: Spanned::new(0..0, 90.),
+            aperture: Spanned::new(0..0, 0.),
+            focus_dist: None,
+            blades: Spanned::new(0..0, 0),
+            blade_rotation: 0.
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, default)]
+struct EnvironmentDesc {
+    kind: EnvironmentKind,
+    file: Option<Spanned<String>>,
+    intensity: Spanned<f32>,
+    rotation: f32,
+    sun_elevation: Spanned<f32>,
+    sun_azimuth: f32,
+    turbidity: Spanned<f32>,
+    sun_size: Spanned<f32>
+}
+
+impl Default for EnvironmentDesc {
+    fn default() -> EnvironmentDesc {
+        EnvironmentDesc {
+            kind: EnvironmentKind::Gradient,
+            file: None,
+            intensity: one_spanned(),
+            rotation: 0.,
+            sun_elevation: Spanned::new(0..0, 45.),
+            sun_azimuth: 0.,
+            turbidity: Spanned::new(0..0, 3.),
+            sun_size: Spanned::new(0..0, 0.53)
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum EnvironmentKind {
+    #[default]
+    Gradient,
+    Map,
+    Sky
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TextureDesc {
+    kind: TextureKind,
+    file: Option<Spanned<String>>,
+    #[serde(default = "yes")]
+    srgb: bool,
+    #[serde(default)]
+    wrap: WrapDesc,
+    #[serde(default = "white")]
+    color: [f32; 3],
+    #[serde(default = "white")]
+    even: [f32; 3],
+    #[serde(default)]
+    odd: [f32; 3],
+    #[serde(default = "white")]
+    high: [f32; 3],
+    #[serde(default)]
+    low: [f32; 3],
+    #[serde(default = "one_spanned")]
+    scale: Spanned<f32>,
+    #[serde(default)]
+    solid: bool,
+    #[serde(default = "one_octave")]
+    octaves: u32
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum TextureKind {
+    Image,
+    Checker,
+    Noise,
+    Constant
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum WrapDesc {
+    #[default]
+    Repeat,
+    Clamp,
+    Mirror
+}
+
+// A color written out or the name of a texture.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorParam {
+    Value([f32; 3]),
+    Texture(String)
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScalarParam {
+    Value(f32),
+    Texture(String)
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaterialDesc {
+    #[serde(default)]
+    kind: MaterialKind,
+    #[serde(default = "white_param")]
+    color: Spanned<ColorParam>,
+    #[serde(default)]
+    reflectivity: f32,
+    #[serde(default)]
+    emissivity: f32,
+    emission: Option<[f32; 3]>,
+    #[serde(default = "glass_ior")]
+    ior: Spanned<f32>,
+    #[serde(default = "one_spanned")]
+    tint_distance: Spanned<f32>,
+    metal: Option<Spanned<String>>,
+    eta: Option<[f32; 3]>,
+    k: Option<[f32; 3]>,
+    roughness: Option<Spanned<ScalarParam>>,
+    metallic: Option<Spanned<ScalarParam>>,
+    specular: Option<Spanned<ScalarParam>>,
+    clearcoat: Option<Spanned<ScalarParam>>,
+    clearcoat_roughness: Option<Spanned<ScalarParam>>,
+    sheen: Option<Spanned<ScalarParam>>,
+    transmission: Option<Spanned<ScalarParam>>,
+    normal_map: Option<Spanned<String>>,
+    bump: Option<Spanned<String>>,
+    #[serde(default = "bump_scale")]
+    bump_scale: f32
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum MaterialKind {
+    #[default]
+    Diffuse,
+    Principled,
+    Dielectric,
+    Conductor
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlaneDesc {
+    point: [f32; 3],
+    normal: Spanned<[f32; 3]>,
+    material: Spanned<String>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SphereDesc {
+    center: [f32; 3],
+    radius: Spanned<f32>,
+    material: Spanned<String>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TriangleDesc {
+    vertices: [[f32; 3]; 3],
+    material: Spanned<String>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MeshDesc {
+    file: Spanned<String>,
+    material: Option<Spanned<String>>,
+    #[serde(default = "smooth_angle")]
+    smooth_angle: f32
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LightDesc {
+    #[serde(default)]
+    kind: LightKind,
+    position: Option<[f32; 3]>,
+    direction: Option<[f32; 3]>,
+    #[serde(default = "white")]
+    color: [f32; 3],
+    #[serde(default = "one")]
+    intensity: f32,
+    #[serde(default)]
+    radius: f32,
+    #[serde(default)]
+    angle: f32,
+    #[serde(default = "spot_inner")]
+    inner_angle: f32,
+    #[serde(default = "spot_outer")]
+    outer_angle: f32,
+    u: Option<[f32; 3]>,
+    v: Option<[f32; 3]>
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum LightKind {
+    #[default]
+    Point,
+    Spot,
+    Directional,
+    Rect,
+    Disk
+}
+
+fn unspanned<T: Default>() -> Spanned<T> {
+    Spanned::new(0..0, T::default())
+}
+
+fn white() -> [f32; 3] {
+    [1., 1., 1.]
+}
+
+fn white_param() -> Spanned<ColorParam> {
+    Spanned::new(0..0, ColorParam::Value([1., 1., 1.]))
+}
+
+fn one() -> f32 {
+    1.
+}
+
+fn yes() -> bool {
+    true
+}
+
+fn one_octave() -> u32 {
+    1
+}
+
+fn one_spanned() -> Spanned<f32> {
+    Spanned::new(0..0, 1.)
+}
+
+fn spot_inner() -> f32 {
+    30.
+}
+
+fn spot_outer() -> f32 {
+    45.
+}
+
+fn smooth_angle() -> f32 {
+    60.
+}
+
+fn bump_scale() -> f32 {
+    0.01
+}
+
+fn glass_ior() -> Spanned<f32> {
+    Spanned::new(0..0, 1.5)
+}
+
+fn vec3(v: [f32; 3]) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+pub fn load_scene(path: &Path) -> Result<Scene, String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    //"file:line: message" for anything we can point at, defaults have empty spans
+    let err = |span: Option<Range<usize>>, msg: &str| match span {
+        Some(span) if !span.is_empty() => format!("{}:{}: {}", path.display(), source[..span.start].matches('\n').count() + 1, msg),
+        _ => format!("{}: {}", path.display(), msg),
+    };
+
+    let file: SceneFile = toml::from_str(&source).map_err(|e| err(e.span(), e.message()))?;
+
+    let scene_dir = path.parent().unwrap_or(Path::new(""));
+    let mut textures: HashMap<&str, Arc<dyn Texture>> = HashMap::new();
+    for (name, t) in &file.texture {
+        let (span, t) = (t.span(), t.get_ref());
+        let scale = *t.scale.get_ref();
+        if scale <= 0. {
+            return Err(err(Some(t.scale.span()), "texture scale must be positive"));
+        }
+        let texture: Arc<dyn Texture> = match t.kind {
+            TextureKind::Image => {
+                let file = t.file.as_ref().ok_or_else(|| err(Some(span), &format!("image texture '{}' needs a file", name)))?;
+                let wrap = match t.wrap {
+                    WrapDesc::Repeat => Wrap::Repeat,
+                    WrapDesc::Clamp => Wrap::Clamp,
+                    WrapDesc::Mirror => Wrap::Mirror,
+                };
+                let image = Image::load(&scene_dir.join(file.get_ref()), t.srgb, wrap).map_err(|e| err(Some(file.span()), &e))?;
+                Arc::new(image)
+            }
+            TextureKind::Checker => Arc::new(Checker { even: vec3(t.even), odd: vec3(t.odd), scale, solid: t.solid }),
+            TextureKind::Noise => Arc::new(Noise::new(vec3(t.low), vec3(t.high), scale, t.octaves)),
+            TextureKind::Constant => constant(vec3(t.color)),
+        };
+        textures.insert(name.as_str(), texture);
+    }
+    let texture = |name: &str, span: Range<usize>| -> Result<Arc<dyn Texture>, String> {
+        textures.get(name).cloned().ok_or_else(|| err(Some(span), &format!("unknown texture '{}'", name)))
+    };
+    let color = |param: &Spanned<ColorParam>| -> Result<Arc<dyn Texture>, String> {
+        match param.get_ref() {
+            ColorParam::Value(c) => Ok(constant(vec3(*c))),
+            ColorParam::Texture(name) => texture(name, param.span()),
+        }
+    };
+    //0 to 1 parameters, `default` when left out
+    let fraction = |param: &Option<Spanned<ScalarParam>>, default: f32, what: &str| -> Result<Arc<dyn Texture>, String> {
+        match param {
+            Some(p) => match p.get_ref() {
+                ScalarParam::Value(v) if !(0. ..=1.).contains(v) => Err(err(Some(p.span()), &format!("material {} must be between 0 and 1", what))),
+                ScalarParam::Value(v) => Ok(constant_scalar(*v)),
+                ScalarParam::Texture(name) => texture(name, p.span()),
+            },
+            None => Ok(constant_scalar(default)),
+        }
+    };
+
+    let mut materials: HashMap<&str, Material> = HashMap::new();
+    for (name, m) in &file.material {
+        let (span, m) = (m.span(), m.get_ref());
+        let ior = *m.ior.get_ref();
+        if ior <= 0. {
+            return Err(err(Some(m.ior.span()), "material ior must be positive"));
+        }
+        let material = match m.kind {
+            MaterialKind::Diffuse => {
+                let mut maps = PrincipledMaps::new(&Principled::diffuse(Vec3::new(1., 1., 1.), m.reflectivity));
+                maps.base_color = color(&m.color)?;
+                Material::principled(maps)
+            }
+            MaterialKind::Principled => {
+                let d = Principled::default();
+                Material::principled(PrincipledMaps {
+                    base_color: color(&m.color)?,
+                    metallic: fraction(&m.metallic, d.metallic, "metallic")?,
+                    roughness: fraction(&m.roughness, d.roughness, "roughness")?,
+                    specular: fraction(&m.specular, d.specular, "specular")?,
+                    clearcoat: fraction(&m.clearcoat, d.clearcoat, "clearcoat")?,
+                    clearcoat_roughness: fraction(&m.clearcoat_roughness, d.clearcoat_roughness, "clearcoat_roughness")?,
+                    sheen: fraction(&m.sheen, d.sheen, "sheen")?,
+                    transmission: fraction(&m.transmission, d.transmission, "transmission")?,
+                    ior
+                })
+            }
+            MaterialKind::Dielectric => {
+                if *m.tint_distance.get_ref() <= 0. {
+                    return Err(err(Some(m.tint_distance.span()), "material tint_distance must be positive"));
+                }
+                let tint = match m.color.get_ref() {
+                    ColorParam::Value(c) => vec3(*c),
+                    ColorParam::Texture(_) => return Err(err(Some(m.color.span()), "dielectric color can't be a texture")),
+                };
+                Material::dielectric(ior, tint, *m.tint_distance.get_ref())
+            }
+            MaterialKind::Conductor => {
+                let roughness = fraction(&m.roughness, 0., "roughness")?;
+                let (eta, k) = match (&m.metal, m.eta, m.k) {
+                    (Some(metal), _, _) => metal_preset(metal.get_ref())
+                        .ok_or_else(|| err(Some(metal.span()), &format!("unknown metal '{}' (gold, copper, aluminum or silver)", metal.get_ref())))?,
+                    (None, Some(eta), Some(k)) => (vec3(eta), vec3(k)),
+                    _ => return Err(err(Some(span), &format!("conductor material '{}' needs a metal preset or both eta and k", name))),
+                };
+                Material::conductor(eta, k, roughness, color(&m.color)?)
+            }
+        };
+        let emission = match (m.emission, m.color.get_ref()) {
+            (Some(e), _) => vec3(e),
+            (None, ColorParam::Value(c)) => vec3(*c),
+            (None, ColorParam::Texture(_)) if m.emissivity > 0. => return Err(err(Some(m.color.span()), "textured glowing materials need an emission color")),
+            (None, ColorParam::Texture(_)) => Vec3::new(0., 0., 0.),
+        };
+        let material = material.with_emission(emission * m.emissivity);
+        let material = match (&m.normal_map, &m.bump) {
+            (Some(_), Some(bump)) => return Err(err(Some(bump.span()), "material can have a normal_map or a bump, not both")),
+            (Some(map), None) => material.with_normal_map(NormalMap::Tangent(texture(map.get_ref(), map.span())?)),
+            (None, Some(bump)) => material.with_normal_map(NormalMap::Bump { height: texture(bump.get_ref(), bump.span())?, scale: m.bump_scale }),
+            (None, None) => material,
+        };
+        materials.insert(name.as_str(), material);
+    }
+    let material = |name: &Spanned<String>| -> Result<Material, String> {
+        materials.get(name.get_ref().as_str()).cloned()
+            .ok_or_else(|| err(Some(name.span()), &format!("unknown material '{}'", name.get_ref())))
+    };
+
+    let mut objects: Vec<Box<dyn Hittable>> = vec![];
+    let mut warnings = vec![];
+    for p in &file.plane {
+        let normal = vec3(*p.normal.get_ref());
+        if normal.length_squared() == 0. {
+            return Err(err(Some(p.normal.span()), "plane normal can't be zero"));
+        }
+        objects.push(Box::new(Plane::new(vec3(p.point), normal, material(&p.material)?)));
+    }
+    for s in &file.sphere {
+        if *s.radius.get_ref() <= 0. {
+            return Err(err(Some(s.radius.span()), "sphere radius must be positive"));
+        }
+        objects.push(Box::new(Sphere::new(vec3(s.center), *s.radius.get_ref(), material(&s.material)?)));
+    }
+    for t in &file.triangle {
+        let [a, b, c] = t.vertices;
+        objects.push(Box::new(Triangle::new(vec3(a), vec3(b), vec3(c), material(&t.material)?)));
+    }
+    for m in &file.mesh {
+        let default_material = match &m.material {
+            Some(name) => material(name)?,
+            None => Material::new(Vec3::new(0.8, 0.8, 0.8), 0., 0.),
+        };
+        let mesh_path = scene_dir.join(m.file.get_ref());
+        let mesh = load_obj(&mesh_path, default_material, m.smooth_angle, &mut warnings).map_err(|e| err(Some(m.file.span()), &e))?;
+        objects.extend(Mesh::triangles(&mesh));
+    }
+
+    let env = file.environment.get_ref();
+    if *env.intensity.get_ref() < 0. {
+        return Err(err(Some(env.intensity.span()), "environment intensity can't be negative"));
+    }
+    let environment = match env.kind {
+        EnvironmentKind::Gradient => Environment::Gradient,
+        EnvironmentKind::Map => {
+            let file = env.file.as_ref().ok_or_else(|| err(Some(file.environment.span()), "environment map needs a file"))?;
+            let map = EnvironmentMap::load(&scene_dir.join(file.get_ref()), *env.intensity.get_ref(), env.rotation)
+                .map_err(|e| err(Some(file.span()), &e))?;
+            Environment::Map(map)
+        }
+        EnvironmentKind::Sky => {
+            let elevation = *env.sun_elevation.get_ref();
+            if !(0. ..=90.).contains(&elevation) {
+                return Err(err(Some(env.sun_elevation.span()), "environment sun_elevation must be between 0 and 90 degrees"));
+            }
+            let turbidity = *env.turbidity.get_ref();
+            if !(1.7..=10.).contains(&turbidity) {
+                return Err(err(Some(env.turbidity.span()), "environment turbidity must be between 1.7 and 10"));
+            }
+            let sun_size = *env.sun_size.get_ref();
+            if !(0. ..=10.).contains(&sun_size) {
+                return Err(err(Some(env.sun_size.span()), "environment sun_size must be between 0 and 10 degrees"));
+            }
+            Environment::Sky(PhysicalSky::new(elevation, env.sun_azimuth, turbidity, sun_size, *env.intensity.get_ref()))
+        }
+    };
+
+    let mut lights: Vec<Box<dyn Light>> = vec![];
+    for spanned in &file.light {
+        let l = spanned.get_ref();
+        let fail = |msg: &str| err(Some(spanned.span()), msg);
+        let color = vec3(l.color);
+        let position = || l.position.map(vec3).ok_or_else(|| fail("light needs a position"));
+        let direction = || match l.direction.map(vec3) {
+            Some(d) if d.length_squared() > 0. => Ok(d),
+            _ => Err(fail("light needs a non-zero direction")),
+        };
+        if l.intensity < 0. || l.radius < 0. {
+            return Err(fail("light intensity and radius can't be negative"));
+        }
+        let light: Box<dyn Light> = match l.kind {
+            LightKind::Point => Box::new(SphereLight::new(position()?, color, l.intensity, l.radius)),
+            LightKind::Spot => {
+                if !(0. ..180.).contains(&l.outer_angle) || l.inner_angle < 0. {
+                    return Err(fail("spot light angles must be between 0 and 180 degrees"));
+                }
+                Box::new(SpotLight::new(position()?, direction()?, color, l.intensity, l.inner_angle, l.outer_angle))
+            }
+            LightKind::Directional => {
+                if !(0. ..180.).contains(&l.angle) {
+                    return Err(fail("directional light angle must be between 0 and 180 degrees"));
+                }
+                let cos_max = (l.angle / 2.).to_radians().cos();
+                Box::new(DistantLight::new(direction()? * -1., color * l.intensity, cos_max))
+            }
+            LightKind::Rect => {
+                let (u, v) = match (l.u, l.v) {
+                    (Some(u), Some(v)) => (vec3(u), vec3(v)),
+                    _ => return Err(fail("rect light needs both edges u and v")),
+                };
+                if Vec3::cross(u, v).length_squared() == 0. {
+                    return Err(fail("rect light edges u and v can't be parallel"));
+                }
+                Box::new(RectLight::new(position()?, u, v, color * l.intensity))
+            }
+            LightKind::Disk => {
+                if l.radius <= 0. {
+                    return Err(fail("disk light needs a radius"));
+                }
+                Box::new(DiskLight::new(position()?, direction()?, l.radius, color * l.intensity))
+            }
+        };
+        lights.push(light);
+    }
+
+    let render = file.render.get_ref();
+    if render.width == 0 || render.height == 0 || render.samples == 0 || render.max_depth == 0 {
+        return Err(err(Some(file.render.span()), "render width, height, samples and max_depth must be at least 1"));
+    }
+    let radius = match (&render.filter_radius, render.filter) {
+        (Some(r), _) if *r.get_ref() <= 0. => return Err(err(Some(r.span()), "render filter_radius must be positive")),
+        (Some(r), _) => *r.get_ref(),
+        (None, FilterKind::Box) => 0.5,
+        (None, FilterKind::Tent) => 1.,
+        (None, FilterKind::Gaussian) => 1.5,
+        (None, FilterKind::Mitchell) => 2.,
+        (None, FilterKind::Lanczos) => 3.,
+    };
+    let filter: Arc<dyn Filter> = match render.filter {
+        FilterKind::Box => Arc::new(BoxFilter { radius }),
+        FilterKind::Tent => Arc::new(TentFilter { radius }),
+        FilterKind::Gaussian => Arc::new(GaussianFilter::new(radius)),
+        FilterKind::Mitchell => Arc::new(MitchellFilter::new(radius)),
+        FilterKind::Lanczos => Arc::new(LanczosFilter { radius }),
+    };
+    let settings = RenderSettings { width: render.width, height: render.height, samples: render.samples, max_depth: render.max_depth, threads: render.threads, seed: render.seed, output: render.output.clone(), sampler: render.sampler, filter };
+
+    let cam = &file.camera;
+    let look_from = vec3(cam.look_from);
+    let look_at = vec3(*cam.look_at.get_ref());
+    let vup = vec3(*cam.vup.get_ref());
+    if (look_at - look_from).length_squared() == 0. {
+        return Err(err(Some(cam.look_at.span()), "camera look_at must differ from look_from"));
+    }
+    if Vec3::cross(vup, look_at - look_from).length_squared() == 0. {
+        return Err(err(Some(cam.vup.span()), "camera vup can't be parallel to the view direction"));
+    }
+    let vfov = *cam.vfov.get_ref();
+    if vfov <= 0. || vfov >= 180. {
+        return Err(err(Some(cam.vfov.span()), "camera vfov must be between 0 and 180 degrees"));
+    }
+    let aperture = *cam.aperture.get_ref();
+    if aperture < 0. {
+        return Err(err(Some(cam.aperture.span()), "camera aperture can't be negative"));
+    }
+    let focus_dist = match &cam.focus_dist {
+        Some(d) if *d.get_ref() <= 0. => return Err(err(Some(d.span()), "camera focus_dist must be positive")),
+        Some(d) => *d.get_ref(),
+        None => (look_at - look_from).length(),
+    };
+    let blades = *cam.blades.get_ref();
+    if blades == 1 || blades == 2 {
+        return Err(err(Some(cam.blades.span()), "camera needs 0 (round) or at least 3 blades"));
+    }
+    let aspect_ratio = settings.width as f32 / settings.height as f32;
+    let camera = Camera::new(look_from, look_at, vup, vfov, aspect_ratio, aperture, focus_dist)
+        .with_blades(blades, cam.blade_rotation);
+
+    let world = World::new(objects, lights).with_environment(environment).with_light_selection(render.light_sampler);
+    Ok(Scene { world, camera, settings, warnings })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    // The error loading `source` gives, with the scene's path shortened to "scene.toml".
+    fn error(name: &str, source: &str) -> String {
+        let path: PathBuf = std::env::temp_dir().join(format!("k-tracer-scene-{}-{}.toml", name, std::process::id()));
+        fs::write(&path, source).unwrap();
+        let result = load_scene(&path);
+        fs::remove_file(&path).unwrap();
+        match result {
+            Ok(_) => panic!("scene loaded"),
+            Err(e) => e.replace(&path.display().to_string(), "scene.toml"),
+        }
+    }
+
+    #[test]
+    fn unknown_material() {
+        let e = error("material", "[[sphere]]\ncenter = [0, 0, -1]\nradius = 0.5\nmaterial = \"chrome\"\n");
+        assert_eq!(e, "scene.toml:4: unknown material 'chrome'");
+    }
+
+    #[test]
+    fn zero_plane_normal() {
+        let e = error("normal", "[material.floor]\ncolor = [1, 1, 1]\n[[plane]]\npoint = [0, 0, 0]\nnormal = [0, 0, 0]\nmaterial = \"floor\"\n");
+        assert_eq!(e, "scene.toml:5: plane normal can't be zero");
+    }
+
+    #[test]
+    fn missing_files() {
+        let path = std::env::temp_dir().join("k-tracer-no-such-scene.toml");
+        match load_scene(&path) {
+            Ok(_) => panic!("scene loaded"),
+            Err(e) => assert!(e.starts_with(&path.display().to_string()), "{}", e),
+        }
+
+        let e = error("mesh", "[[mesh]]\nfile = \"no-such-mesh.obj\"\n");
+        assert!(e.starts_with("scene.toml:2: "), "{}", e);
+        let e = error("texture", "[texture.wood]\nkind = \"image\"\n");
+        assert_eq!(e, "scene.toml:1: image texture 'wood' needs a file");
+        let e = error("environment", "[render]\nsamples = 4\n\n[environment]\nkind = \"map\"\n");
+        assert_eq!(e, "scene.toml:4: environment map needs a file");
+    }
+
+    #[test]
+    fn tables_and_defaults() {
+        let e = error("conductor", "[material.metal]\nkind = \"conductor\"\n");
+        assert_eq!(e, "scene.toml:1: conductor material 'metal' needs a metal preset or both eta and k");
+        let e = error("render", "[camera]\nvfov = 60\n\n[render]\nsamples = 0\n");
+        assert_eq!(e, "scene.toml:4: render width, height, samples and max_depth must be at least 1");
+        //the default look_at equals this look_from, nothing in the file to point at
+        let e = error("camera", "[camera]\nlook_from = [0, 0, -1]\n");
+        assert_eq!(e, "scene.toml: camera look_at must differ from look_from");
+    }
+}
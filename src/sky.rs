@@ -0,0 +1,163 @@
+use std::f32::consts::PI;
+
+use crate::vec3::*;
+use crate::light::*;
+use crate::texture::*;
+use crate::environment::*;
+
+/*
+Clear daylight from Preetham, Shirley and Smits, "A Practical Analytic Model for Daylight"
+(1999), plus the sun as a small disk dimmed and reddened by the air it shines through.
+
+Radiance comes out in units of 20 kcd/m^2, which puts a clear zenith a bit under 1 and the
+sun around 4 times as bright on the ground as the rest of the sky. `turbidity` is haze,
+1.7 is very clear and 10 is close to overcast.
+
+The model only covers the sky, below the horizon is a grey ground lit by it and the sun.
+The sky gets baked into an environment map so it can be importance sampled like one.
+*/
+
+//kcd/m^2 per unit of renderer radiance
+const SKY_UNIT: f32 = 20.;
+//sun illuminance above the atmosphere, about 128 klx, in the same units
+const SUN_IRRADIANCE: f32 = 6.4;
+const BAKE_WIDTH: usize = 512;
+const GROUND_ALBEDO: f32 = 0.3;
+
+pub struct PhysicalSky {
+    // Unit direction toward the sun.
+    pub sun: Vec3,
+    pub sun_irradiance: Vec3,
+    // Cosine of the sun's angular radius, 1 for a point.
+    pub cos_sun: f32,
+    map: EnvironmentMap
+}
+
+impl PhysicalSky {
+    // `elevation` and `azimuth` of the sun in degrees, azimuth 0 puts it toward -z and 90
+    // toward +x. `sun_size` is its angular diameter in degrees, 0.53 for the real one.
+    pub fn new(elevation: f32, azimuth: f32, turbidity: f32, sun_size: f32, intensity: f32) -> PhysicalSky {
+        let (el, az) = (elevation.to_radians(), azimuth.to_radians());
+        let sun = Vec3::new(el.cos() * az.sin(), el.sin(), -el.cos() * az.cos());
+        let model = Preetham::new(sun, turbidity);
+
+        let (width, height) = (BAKE_WIDTH, BAKE_WIDTH / 2);
+        let mut pixels = vec![Vec3::new(0., 0., 0.); width * height];
+        //sky irradiance on the ground, summed while baking the upper half
+        let mut ground = Vec3::new(0., 0., 0.);
+        let d_omega = (2. * PI / width as f32) * (PI / height as f32);
+        for y in 0..height / 2 {
+            for x in 0..width {
+                let dir = equirect_direction((x as f32 + 0.5) / width as f32, (y as f32 + 0.5) / height as f32);
+                let radiance = model.radiance(dir) * intensity;
+                pixels[y * width + x] = radiance;
+                //cos theta times the row's sin theta
+                ground = ground + radiance * (dir.y * (1. - dir.y * dir.y).sqrt() * d_omega);
+            }
+        }
+        let sun_irradiance = transmittance(el, turbidity) * (SUN_IRRADIANCE * intensity);
+        ground = (ground + sun_irradiance * el.sin().max(0.)) * (GROUND_ALBEDO / PI);
+        for p in &mut pixels[width * (height / 2)..] {
+            *p = ground;
+        }
+
+        let image = Image { width, height, pixels, wrap_u: Wrap::Repeat, wrap_v: Wrap::Clamp };
+        let cos_sun = (sun_size.max(0.) / 2.).to_radians().cos();
+        PhysicalSky { sun, sun_irradiance, cos_sun, map: EnvironmentMap::new(image, 1., 0.) }
+    }
+
+    // The sun's disk isn't included, escaping rays see it through the sun light.
+    pub fn radiance(&self, dir: Vec3) -> Vec3 {
+        self.map.radiance(dir)
+    }
+
+    pub fn sample(&self, a: f32, b: f32) -> Option<LightSample> {
+        self.map.sample(a, b)
+    }
+
+    pub fn pdf(&self, dir: Vec3) -> f32 {
+        self.map.pdf(dir)
+    }
+
+    // Nothing to light once it has set.
+    pub fn sun_light(&self) -> Option<Box<dyn Light>> {
+        if self.sun.y <= 0. {
+            return None;
+        }
+        Some(Box::new(DistantLight::new(self.sun, self.sun_irradiance, self.cos_sun)))
+    }
+}
+
+// Preetham's fit for one quantity (Y, x or y): its value at the zenith and the Perez
+// distribution coefficients A to E.
+struct Perez {
+    zenith: f32,
+    coeffs: [f32; 5]
+}
+
+impl Perez {
+    fn f(&self, cos_theta: f32, gamma: f32) -> f32 {
+        let [a, b, c, d, e] = self.coeffs;
+        let cos_gamma = gamma.cos();
+        (1. + a * (b / cos_theta.max(1e-3)).exp()) * (1. + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+    }
+}
+
+struct Preetham {
+    sun: Vec3,
+    theta_sun: f32,
+    lum: Perez,
+    x: Perez,
+    y: Perez
+}
+
+impl Preetham {
+    fn new(sun: Vec3, t: f32) -> Preetham {
+        let theta = sun.y.clamp(0., 1.).acos();
+        let (t2, th2, th3) = (t * t, theta * theta, theta * theta * theta);
+
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * theta);
+        let zenith_lum = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let zenith_x = t2 * (0.00166 * th3 - 0.00375 * th2 + 0.00209 * theta)
+            + t * (-0.02903 * th3 + 0.06377 * th2 - 0.03202 * theta + 0.00394)
+            + (0.11693 * th3 - 0.21196 * th2 + 0.06052 * theta + 0.25886);
+        let zenith_y = t2 * (0.00275 * th3 - 0.00610 * th2 + 0.00317 * theta)
+            + t * (-0.04214 * th3 + 0.08970 * th2 - 0.04153 * theta + 0.00516)
+            + (0.15346 * th3 - 0.26756 * th2 + 0.06670 * theta + 0.26688);
+
+        let lum = Perez { zenith: zenith_lum, coeffs: [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703] };
+        let x = Perez { zenith: zenith_x, coeffs: [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452] };
+        let y = Perez { zenith: zenith_y, coeffs: [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529] };
+        Preetham { sun, theta_sun: theta, lum, x, y }
+    }
+
+    // Linear sRGB radiance of the sky in unit direction `dir` (above the horizon).
+    fn radiance(&self, dir: Vec3) -> Vec3 {
+        let cos_theta = dir.y;
+        let gamma = Vec3::dot(dir, self.sun).clamp(-1., 1.).acos();
+        //each quantity relative to the zenith
+        let at = |p: &Perez| p.zenith * p.f(cos_theta, gamma) / p.f(1., self.theta_sun);
+        let (lum, x, y) = (at(&self.lum).max(0.) / SKY_UNIT, at(&self.x), at(&self.y));
+        if y <= 0. {
+            return Vec3::new(0., 0., 0.);
+        }
+
+        //xyY to XYZ to linear sRGB
+        let (cx, cz) = (x / y * lum, (1. - x - y) / y * lum);
+        Vec3::new(
+            (3.2406 * cx - 1.5372 * lum - 0.4986 * cz).max(0.),
+            (-0.9689 * cx + 1.8758 * lum + 0.0415 * cz).max(0.),
+            (0.0557 * cx - 0.2040 * lum + 1.0570 * cz).max(0.))
+    }
+}
+
+// How much of the sun gets through the atmosphere at `elevation` radians, per channel
+// (Rayleigh and aerosol scattering at 680, 550 and 440 nm, Preetham's appendix).
+fn transmittance(elevation: f32, turbidity: f32) -> Vec3 {
+    let theta = PI / 2. - elevation.max(0.);
+    //relative optical air mass, Kasten's fit that stays finite at the horizon
+    let mass = 1. / (theta.cos() + 0.15 * (93.885 - theta.to_degrees()).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let channel = |lambda: f32| (-mass * (0.008735 * lambda.powf(-4.08) + beta * lambda.powf(-1.3))).exp();
+    Vec3::new(channel(0.68), channel(0.55), channel(0.44))
+}
@@ -0,0 +1,222 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::vec3::*;
+use crate::util::*;
+
+/*
+Anything a material parameter can be read from. Textures are looked up with the hit's (u, v)
+and its world position `p`; scalar parameters use the average of the three channels.
+
+uv (0, 0) is the bottom left of an image, like OBJ's vt.
+*/
+pub trait Texture: Send + Sync {
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3;
+
+    fn scalar(&self, u: f32, v: f32, p: Vec3) -> f32 {
+        let c = self.value(u, v, p);
+        (c.x + c.y + c.z) / 3.
+    }
+}
+
+pub struct Constant {
+    pub color: Vec3
+}
+
+impl Texture for Constant {
+    fn value(&self, _u: f32, _v: f32, _p: Vec3) -> Vec3 {
+        self.color
+    }
+}
+
+pub fn constant(color: Vec3) -> Arc<dyn Texture> {
+    Arc::new(Constant { color })
+}
+
+pub fn constant_scalar(x: f32) -> Arc<dyn Texture> {
+    constant(Vec3::new(x, x, x))
+}
+
+// Alternating squares, `scale` of them per unit of uv, or cubes per world unit when `solid`.
+pub struct Checker {
+    pub even: Vec3,
+    pub odd: Vec3,
+    pub scale: f32,
+    pub solid: bool
+}
+
+impl Texture for Checker {
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3 {
+        let cells = if self.solid {
+            (p.x * self.scale).floor() + (p.y * self.scale).floor() + (p.z * self.scale).floor()
+        } else {
+            (u * self.scale).floor() + (v * self.scale).floor()
+        };
+        if cells.rem_euclid(2.) < 1. { self.even } else { self.odd }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Wrap {
+    Repeat,
+    Clamp,
+    Mirror
+}
+
+// Bilinearly filtered picture, stored as linear floats.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+    pub wrap_u: Wrap,
+    pub wrap_v: Wrap
+}
+
+impl Image {
+    // PNG, JPEG, Radiance HDR or OpenEXR. Float formats are taken as linear, 8 and 16 bit
+    // ones are decoded from sRGB unless `srgb` is off (roughness maps and other data).
+    pub fn load(path: &Path, srgb: bool, wrap: Wrap) -> Result<Image, String> {
+        let img = image::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let float = matches!(img, image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_));
+        let rgb = img.to_rgb32f();
+        let decode = |x: f32| if srgb && !float { srgb_to_linear(x) } else { x };
+        let pixels = rgb.pixels().map(|p| Vec3::new(decode(p[0]), decode(p[1]), decode(p[2]))).collect();
+        Ok(Image { width: rgb.width() as usize, height: rgb.height() as usize, pixels, wrap_u: wrap, wrap_v: wrap })
+    }
+
+    pub fn with_wrap_v(mut self, wrap: Wrap) -> Image {
+        self.wrap_v = wrap;
+        self
+    }
+
+    // Texel at integer coordinates, wrapped into the image. y goes down from the top row.
+    pub fn texel(&self, x: i64, y: i64) -> Vec3 {
+        let x = wrap_index(x, self.width, self.wrap_u);
+        let y = wrap_index(y, self.height, self.wrap_v);
+        self.pixels[y * self.width + x]
+    }
+}
+
+impl Texture for Image {
+    fn value(&self, u: f32, v: f32, _p: Vec3) -> Vec3 {
+        //texel centers sit at half integers
+        let x = u * self.width as f32 - 0.5;
+        let y = (1. - v) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = self.texel(x0, y0) * (1. - fx) + self.texel(x0 + 1, y0) * fx;
+        let bottom = self.texel(x0, y0 + 1) * (1. - fx) + self.texel(x0 + 1, y0 + 1) * fx;
+        top * (1. - fy) + bottom * fy
+    }
+}
+
+fn wrap_index(i: i64, n: usize, wrap: Wrap) -> usize {
+    let n = n as i64;
+    let i = match wrap {
+        Wrap::Repeat => i.rem_euclid(n),
+        Wrap::Clamp => i.clamp(0, n - 1),
+        Wrap::Mirror => {
+            let period = i.rem_euclid(2 * n);
+            if period < n { period } else { 2 * n - 1 - period }
+        }
+    };
+    i as usize
+}
+
+fn srgb_to_linear(x: f32) -> f32 {
+    if x <= 0.04045 { x / 12.92 } else { ((x + 0.055) / 1.055).powf(2.4) }
+}
+
+// Perlin noise in world space blended from `low` to `high`. More than one octave sums
+// finer and fainter copies on top (fBm).
+pub struct Noise {
+    pub low: Vec3,
+    pub high: Vec3,
+    pub scale: f32,
+    pub octaves: u32,
+    perlin: Perlin
+}
+
+impl Noise {
+    pub fn new(low: Vec3, high: Vec3, scale: f32, octaves: u32) -> Noise {
+        Noise { low, high, scale, octaves: octaves.max(1), perlin: Perlin::new() }
+    }
+}
+
+impl Texture for Noise {
+    fn value(&self, _u: f32, _v: f32, p: Vec3) -> Vec3 {
+        let t = clamp(0.5 * (self.perlin.fbm(p * self.scale, self.octaves) + 1.), 0., 1.);
+        self.low * (1. - t) + self.high * t
+    }
+}
+
+// Ken Perlin's improved noise (2002). The permutation comes from a fixed seed so every render
+// gets the same pattern.
+struct Perlin {
+    perm: [u8; 512]
+}
+
+impl Perlin {
+    fn new() -> Perlin {
+        let mut table: Vec<u8> = (0..=255).collect();
+        for i in (1..256).rev() {
+            let j = (mix_seed(0x5eed, i as u64) % (i as u64 + 1)) as usize;
+            table.swap(i, j);
+        }
+        let mut perm = [0; 512];
+        for (i, p) in perm.iter_mut().enumerate() {
+            *p = table[i & 255];
+        }
+        Perlin { perm }
+    }
+
+    // Roughly -1 to 1.
+    fn noise(&self, p: Vec3) -> f32 {
+        let (fx, fy, fz) = (p.x.floor(), p.y.floor(), p.z.floor());
+        let (x, y, z) = (p.x - fx, p.y - fy, p.z - fz);
+        let (xi, yi, zi) = ((fx as i64 & 255) as usize, (fy as i64 & 255) as usize, (fz as i64 & 255) as usize);
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+        let perm = &self.perm;
+
+        let a = perm[xi] as usize + yi;
+        let (aa, ab) = (perm[a] as usize + zi, perm[a + 1] as usize + zi);
+        let b = perm[xi + 1] as usize + yi;
+        let (ba, bb) = (perm[b] as usize + zi, perm[b + 1] as usize + zi);
+
+        lerp(w,
+            lerp(v,
+                lerp(u, grad(perm[aa], x, y, z), grad(perm[ba], x - 1., y, z)),
+                lerp(u, grad(perm[ab], x, y - 1., z), grad(perm[bb], x - 1., y - 1., z))),
+            lerp(v,
+                lerp(u, grad(perm[aa + 1], x, y, z - 1.), grad(perm[ba + 1], x - 1., y, z - 1.)),
+                lerp(u, grad(perm[ab + 1], x, y - 1., z - 1.), grad(perm[bb + 1], x - 1., y - 1., z - 1.))))
+    }
+
+    fn fbm(&self, p: Vec3, octaves: u32) -> f32 {
+        let (mut sum, mut amplitude, mut frequency, mut total) = (0., 1., 1., 0.);
+        for _ in 0..octaves {
+            sum += amplitude * self.noise(p * frequency);
+            total += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.;
+        }
+        sum / total
+    }
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    a + t * (b - a)
+}
+
+// Dot product with one of 12 cube edge gradients picked by the hash.
+fn grad(hash: u8, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
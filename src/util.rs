@@ -3,20 +3,18 @@ use crate::vec3::*;
 pub fn clamp(x: f32, min: f32, max: f32) -> f32 {
     if x < min {return min};
     if x > max {return max};
-    return x;
+    x
 }
 
-pub fn angleAxis3x3(angle: f32, axis: Vec3) -> Vec<Vec3> {
-    let c = angle.cos();
-    let s = angle.sin();
-
-    let t = 1. - c;
-    let x = axis.x;
-    let y = axis.y;
-    let z = axis.z;
+// Rec. 709 luminance of a linear color.
+pub fn luminance(c: Vec3) -> f32 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
 
-    return vec![Vec3::new(t * x * x + c,      t * x * y - s * z,  t * x * z + s * y),
-                Vec3::new(t * x * y + s * z,  t * y * y + c,      t * y * z - s * x),
-                Vec3::new(t * x * z - s * y,  t * y * z + s * x,  t * z * z + c)
-    ];
-}
\ No newline at end of file
+// SplitMix64 finalizer. Folds a value into a seed to derive independent RNG streams.
+pub fn mix_seed(seed: u64, value: u64) -> u64 {
+    let mut z = seed ^ value.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
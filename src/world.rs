@@ -1,283 +1,171 @@
-use std::f32::consts::PI;
-
-use crate::primitives::*;
+use crate::hittable::*;
+use crate::bvh::*;
 use crate::vec3::*;
 use crate::ray::*;
 use crate::light::*;
-use crate::util::*;
+use crate::environment::*;
+use crate::sampling::*;
+use crate::light_sampler::*;
 
 pub struct World {
 
-    pub planes: Vec<Plane>,
-    pub spheres: Vec<Sphere>,
-    pub triangles: Vec<Triangle>,
-    pub lights: Vec<Light>,
+    pub bvh: Bvh,
+    //unbounded shapes (planes) can't go in the BVH and are tested one by one
+    pub planes: Vec<Box<dyn Hittable>>,
+    //scene lights plus the ones emissive objects hand us
+    pub lights: Vec<Box<dyn Light>>,
+    //what escaping rays see
+    pub environment: Environment,
+    //lights escaping rays can run into, like the sun
+    infinite: Vec<usize>,
+    //picks the light for `direct_light`, the environment being the one after `lights`
+    selection: LightSelection,
+    light_sampler: LightSampler,
 
 }
 
-impl World {
-    pub fn new(planes: Vec<Plane>, spheres: Vec<Sphere>, triangles: Vec<Triangle>, lights: Vec<Light>) -> World {
-        World { planes, spheres, triangles, lights}
-    }
-
-    pub fn hit(world: &World, ray: &Ray) -> Vec4 {
-        let mut worldLights: Vec<Light> = vec![];
-        for l in 0..world.lights.len() {
-            worldLights.push(world.lights[l]);
-        }
-        let mut t_buffer: Vec<f32> = vec![];
-        let mut small_t_index: usize = 0;
-        let mut small_t: f32 = 0.;
-        let mut type_buffer: Vec<Vec3> = vec![];
-        let mut vote: f32 = 0.;
-        //type buffer stores Vec3([primitive type],[index in world object list for said primitive type],[0.0])
-
-        fn vote_for_sky(votes: f32, world: &World, ray: &Ray) -> Vec4 {
-            if votes == (world.triangles.len()+world.spheres.len()+world.planes.len()) as f32 {
-                return RenderSky(ray);
-            } else {
-                return Vec4::new(-1., -1., -1., -1.);
-            }
-        }
+// The last scattering event of a path: where it was, its shading normal and the BSDF's pdf for
+// the direction it left in. Light sampling would have been done from there too.
+#[derive(Clone, Copy)]
+pub struct Bounce {
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub pdf: f32
+}
 
-        for i in 0..world.triangles.len() {
-            let tT = world.triangles[i].t(&ray);
-            if tT > 0. {
-                t_buffer.push(tT);
-                type_buffer.push(Vec3::new(0.,i as f32,0.));
-            }else {
-                vote += 1.;
-            }
-        }
-        for i in 0..world.spheres.len() {
-            if world.spheres[i].material.emissivity > 0. {
-                worldLights.push(Light::new(world.spheres[i].center, world.spheres[i].material.color, world.spheres[i].material.emissivity * 5., world.spheres[i].radius + 99.));
-            }
-            let tS = world.spheres[i].t(&ray);
-            if tS > 0. {
-                t_buffer.push(tS);
-                type_buffer.push(Vec3::new(1.,i as f32,0.));
-            }else {
-                vote += 1.;
-            }
-        }
-        for i in 0..world.planes.len() {
-            let tP = world.planes[i].t(&ray);
-            if tP > 0. {
-                t_buffer.push(tP);
-                type_buffer.push(Vec3::new(2.,i as f32,0.));
-            }else {
-                vote += 1.;
+impl World {
+    pub fn new(objects: Vec<Box<dyn Hittable>>, lights: Vec<Box<dyn Light>>) -> World {
+        let (mut objects, mut lights) = (objects, lights);
+        for o in objects.iter_mut() {
+            if let Some(light) = o.emitter() {
+                o.attach_light(lights.len());
+                lights.push(light);
             }
         }
-        
-        let vfs = vote_for_sky(vote, world, ray);
+        let (bounded, planes): (Vec<_>, Vec<_>) = objects.into_iter().partition(|o| o.bounding_box().is_some());
+        let mut world = World {
+            bvh: Bvh::new(bounded),
+            planes,
+            lights,
+            environment: Environment::Gradient,
+            infinite: vec![],
+            selection: LightSelection::Bvh,
+            light_sampler: LightSampler::new(LightSelection::Uniform, &[])
+        };
+        world.update_lights();
+        world
+    }
 
-        if vfs != Vec4::new(-1., -1., -1., -1.) {
-            return vfs;
-        }
+    pub fn with_environment(mut self, environment: Environment) -> World {
+        self.lights.extend(environment.emitter());
+        self.environment = environment;
+        self.update_lights();
+        self
+    }
 
-        small_t = (t_buffer.iter().fold(f64::INFINITY, |a, &b| a.min(b.into()))) as f32;
-        while t_buffer[small_t_index] != small_t {
-            small_t_index += 1;
-        }
+    pub fn with_light_selection(mut self, selection: LightSelection) -> World {
+        self.selection = selection;
+        self.update_lights();
+        self
+    }
 
-        let mut dir_to_light: Vec<Vec3> = vec![];
-        for l in 0..worldLights.len() {
-            dir_to_light.push(Vec3::unit_vec(worldLights[l].position - (ray.origin() + (ray.direction()*small_t))));
+    fn update_lights(&mut self) {
+        self.infinite = (0..self.lights.len()).filter(|&i| self.lights[i].is_infinite()).collect();
+        let mut bounds: Vec<Option<LightBounds>> = self.lights.iter().map(|l| l.bounds()).collect();
+        if self.environment.is_sampled() {
+            bounds.push(None);
         }
+        self.light_sampler = LightSampler::new(self.selection, &bounds);
+    }
 
-        if type_buffer[small_t_index].x == 0. {
-            return RenderTriangle(type_buffer[small_t_index].y, world, ray, dir_to_light, worldLights);
-        }else if type_buffer[small_t_index].x == 1. {
-            return RenderSphere(type_buffer[small_t_index].y, world, ray, dir_to_light, worldLights);
-        }else {
-            return RenderPlane(type_buffer[small_t_index].y, world, ray, dir_to_light, worldLights);
+    // Closest hit over every object in the scene.
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let mut closest = t_max;
+        let mut record = self.bvh.hit(ray, t_min, t_max);
+        if let Some(rec) = &record {
+            closest = rec.t;
         }
-
-        fn RenderTriangle(t: f32, world: &World, ray: &Ray, dir_to_light: Vec<Vec3>, worldLights: Vec<Light>) -> Vec4 {
-            let tempy = world.triangles[t as usize].hit(&ray);
-            if tempy[0].z == 1. {
-                let mut return_buffer:Vec<Vec4> = vec![];
-                let mut hitT = Vec3::new(0.,0.,0.);
-                let mut normalT = Vec3::new(0.,0.,0.);
-                for l in 0..worldLights.len() {
-                    //DIFFUSE SHADER 2.0 :: Better Lighting
-                    //https://medium.com/@alexander.wester/ray-tracing-soft-shadows-in-real-time-a53b836d123b
-                    let t1 = tempy[0].w;
-                    hitT = ray.origin() + (ray.direction() * t1);
-                    let mut perpL = Vec3::cross(dir_to_light[l], Vec3::new(0.,1.,0.));
-                    if perpL == Vec3::new(0.,0.,0.) {perpL.x = 1.;}
-                    let to_light_edge = Vec3::unit_vec((worldLights[l].position + perpL * worldLights[l].radius) - hitT);
-                    let coneAngle = (Vec3::dot(dir_to_light[l], to_light_edge)).acos() * 2.;
-                    normalT = tempy[1].to_Vec3() * -1.;
-                    let shadow_ray = Ray::ray(hitT + (normalT * 0.00001), getConeSample(dir_to_light[l], coneAngle));
-                    let mut light_intensity: f32 = worldLights[l].intensity;
-                    for i in 0..world.spheres.len() {
-                        if Sphere::hit(world.spheres[i], &shadow_ray) > 0. {
-                            light_intensity *= 0.1;
-                        }
-                    }
-                    for i in 0..world.triangles.len() {
-                        if i as f32 != t {
-                            let thit = Triangle::hit(world.triangles[i], &shadow_ray);
-                            if thit[0].z > 0. {
-                                light_intensity *= 0.1;
-                            }
-                        }
-                    }
-                    
-                    let light_powT = Vec3::dot(normalT, dir_to_light[l]).max(0.0) * light_intensity;
-                    let tempColT = world.triangles[t as usize].material.color.clone() * worldLights[l].color.clone() * light_powT;
-                    return_buffer.push(Vec4::new(tempColT.x, tempColT.y, tempColT.z, 1.));
-                }
-                let mut r = Vec4::new(0.,0.,0.,0.);
-                for b in 0..return_buffer.len() {
-                    r = r + return_buffer[b];
-                }
-                r = r / return_buffer.len() as f32;
-                let mut color = r.to_Vec3();
-                let reflectivity = clamp(world.triangles[t as usize].material.reflectivity, 0., 1.);
-                if reflectivity > 0. {
-                    let bounce_ray = Ray::reflect(normalT, ray.direction(), hitT, -0.000001);
-                    color = color * (1. - reflectivity);
-                    let tempHit = World::hit(world, &bounce_ray);
-                    color = color + (tempHit.to_Vec3() * reflectivity);
-                }
-                return Vec4::new(color.x, color.y, color.z, 1.);
+        for plane in &self.planes {
+            if let Some(rec) = plane.hit(ray, t_min, closest) {
+                closest = rec.t;
+                record = Some(rec);
             }
-            return RenderSky(ray);
         }
+        record
+    }
 
-        fn RenderSphere(s: f32, world: &World, ray: &Ray, dir_to_light: Vec<Vec3>, worldLights: Vec<Light>) -> Vec4 {
-            let mut return_buffer:Vec<Vec4> = vec![];
-            let mut hitS = Vec3::new(0.,0.,0.);
-            let mut normalS = Vec3::new(0.,0.,0.);
-            for l in 0..worldLights.len() {
-                if world.spheres[s as usize].material.emissivity > 0. {
-                    return (world.spheres[s as usize].material.color * world.spheres[s as usize].material.emissivity).to_Vec4(1.);
-                }
-                let t1 = world.spheres[s as usize].hit(&ray);
-                hitS = ray.at(t1);
-                let mut perpL = Vec3::cross(dir_to_light[l], Vec3::new(0.,1.,0.));
-                if perpL == Vec3::new(0.,0.,0.) {perpL.x = 1.;}
-                let toLightEdge = Vec3::unit_vec((worldLights[l].position + perpL * worldLights[l].radius) - hitS);
-                let coneAngle = (Vec3::dot(dir_to_light[l], toLightEdge)).acos() * 2.;
-                normalS = Vec3::unit_vec(ray.at(t1) - world.spheres[s as usize].center);
-                let shadow_ray = Ray::ray(hitS + (normalS * 0.00001), getConeSample(dir_to_light[l], coneAngle));
-                let mut light_intensity: f32 = worldLights[l].intensity;
-                for i in 0..world.spheres.len() {
-                    if i as f32 != s {
-                        if Sphere::hit(world.spheres[i], &shadow_ray) > 0. {
-                            light_intensity *= 0.1;
-                        }
-                    }
-                }
-                for i in 0..world.triangles.len() {
-                    let thit = Triangle::hit(world.triangles[i], &shadow_ray);
-                    if thit[0].z > 0. {
-                        light_intensity *= 0.1;
-                    }
-                }
+    // True if anything blocks the ray between t_min and t_max.
+    pub fn occluded(&self, ray: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.bvh.occluded(ray, t_min, t_max) || self.planes.iter().any(|p| p.hit(ray, t_min, t_max).is_some())
+    }
 
-                let light_powS = Vec3::dot(normalS, dir_to_light[l]).max(0.0) * light_intensity;
-                let tempColS = world.spheres[s as usize].material.color.clone() * worldLights[l].color.clone() * light_powS;
-                return_buffer.push(Vec4::new(tempColS.x, tempColS.y, tempColS.z, 1.));
-            }
-            let mut r = Vec4::new(0.,0.,0.,0.);
-            for b in 0..return_buffer.len() {
-                r = r + return_buffer[b];
-            }
-            r = r / return_buffer.len() as f32;
-            let mut color = r.to_Vec3();
-            let reflectivity = clamp(world.spheres[s as usize].material.reflectivity, 0., 1.);
-            if reflectivity > 0. {
-                let bounce_ray = Ray::reflect(normalS, ray.direction(), hitS, -0.000001);
-                color = color * (1. - reflectivity);
-                let tempHit = World::hit(world, &bounce_ray);
-                color = color + (tempHit.to_Vec3() * reflectivity);
-            }
-            return Vec4::new(color.x, color.y, color.z, 1.);
+    // Light reflected toward the viewer straight from the scene's lights, `f` giving the BSDF
+    // and its pdf for an incoming direction. The light sampler picks one light, the environment
+    // counting as one when it's sampled, and it's sampled with a shadow ray that stops at the
+    // sampled point. Lights a BSDF sample could also hit are weighed against that with the
+    // power heuristic, see `emission` and `escaped` for the other half.
+    // `pick` chooses the light and (a, b) the point on it.
+    pub fn direct_light<F: Fn(Vec3) -> (Vec3, f32)>(&self, rec: &HitRecord, pick: f32, (a, b): (f32, f32), f: F) -> Vec3 {
+        let (i, pmf) = match self.light_sampler.sample(rec.point, rec.shading_normal, pick) {
+            Some(picked) => picked,
+            None => return Vec3::new(0., 0., 0.),
+        };
+        let (sample, visible) = match self.lights.get(i) {
+            Some(l) => (l.sample_li(rec.point, a, b), l.is_visible()),
+            None => (self.environment.sample(a, b), true),
+        };
+        let sample = match sample {
+            Some(s) => s,
+            None => return Vec3::new(0., 0., 0.),
+        };
+        let (bsdf, bsdf_pdf) = f(sample.wi);
+        if bsdf.x + bsdf.y + bsdf.z <= 0. || !self.unshadowed(rec, &sample) {
+            return Vec3::new(0., 0., 0.);
         }
+        //lights behind the surface only count for materials that let light through
+        let cos = Vec3::dot(rec.shading_normal, sample.wi).abs();
+        let mis = if visible && sample.pdf > 0. { power_heuristic(pmf * sample.pdf, bsdf_pdf) } else { 1. };
+        bsdf * sample.weight * (cos * mis / pmf)
+    }
 
-        fn RenderPlane(p: f32, world: &World, ray: &Ray, dir_to_light: Vec<Vec3>, worldLights: Vec<Light>) -> Vec4 {
-            let mut return_buffer:Vec<Vec4> = vec![];
-            let mut hitP = Vec3::new(0.,0.,0.);
-            let mut normalP = Vec3::new(0.,0.,0.);
-            for l in 0..worldLights.len() {
-                let t1 = world.planes[p as usize].hit(&ray);
-                hitP = ray.origin() + (ray.direction() * t1);
-                let mut perpL = Vec3::cross(dir_to_light[l], Vec3::new(0.,1.,0.));
-                if perpL == Vec3::new(0.,0.,0.) {perpL.x = 1.;}
-                let toLightEdge = Vec3::unit_vec((worldLights[l].position + perpL * worldLights[l].radius) - hitP);
-                let coneAngle = (Vec3::dot(dir_to_light[l], toLightEdge)).acos() * 2.;
-                normalP = Vec3::unit_vec(world.planes[p as usize].normal * -1.);
-                let shadow_ray = Ray::ray(hitP + (normalP * 0.00001), getConeSample(dir_to_light[l], coneAngle));
-                let mut light_intensity: f32 = worldLights[l].intensity;
-                for i in 0..world.spheres.len() {
-                    if Sphere::hit(world.spheres[i], &shadow_ray) > 0. {
-                        light_intensity *= 0.1;
-                    }
-                }
-                for i in 0..world.triangles.len() {
-                    let thit = Triangle::hit(world.triangles[i], &shadow_ray);
-                    if thit[0].z > 0. {
-                        light_intensity *= 0.1;
-                    }
-                }
-
-                
-                let light_powP = Vec3::dot(normalP, dir_to_light[l]).max(0.0) * light_intensity;
-                let tempColP = world.planes[p as usize].material.color.clone() * worldLights[l].color.clone() * light_powP;
-                return_buffer.push(Vec4::new(tempColP.x, tempColP.y, tempColP.z, 1.));
-            }
-            let mut r = Vec4::new(0.,0.,0.,0.);
-            for b in 0..return_buffer.len() {
-                r = r + return_buffer[b];
+    // Emission of the surface at `rec`, seen along `ray`. `from` is the bounce that sent `ray`,
+    // None for camera rays and specular bounces that light sampling can't reproduce.
+    pub fn emission(&self, rec: &HitRecord, ray: &Ray, from: Option<Bounce>) -> Vec3 {
+        let emitted = rec.material.emitted();
+        match (from, rec.light) {
+            (Some(from), Some(i)) => {
+                let pmf = self.light_sampler.pmf(from.point, from.normal, i);
+                let light_pdf = pmf * self.lights[i].pdf(from.point, Vec3::unit_vec(ray.direction()));
+                emitted * power_heuristic(from.pdf, light_pdf)
             }
-            r = r / return_buffer.len() as f32;
-                let mut color = r.to_Vec3();
-                let reflectivity = clamp(world.planes[p as usize].material.reflectivity, 0., 1.);
-                if reflectivity > 0. {
-                    let bounce_ray = Ray::reflect(normalP, ray.direction(), hitP, -0.000001);
-                    color = color * (1. - reflectivity);
-                    let tempHit = World::hit(world, &bounce_ray);
-                    color = color + (tempHit.to_Vec3() * reflectivity);
-                }
-                return Vec4::new(color.x, color.y, color.z, 1.);
+            _ => emitted,
         }
+    }
 
-        fn RenderSky(ray: &Ray) -> Vec4 {
-            let unit_dir: Vec3 = Vec3::unit_vec(ray.direction());
-            let t: f32 = 0.5 * (unit_dir.y + 1.0);
-            
-            let tempColSky = Vec3::new(1. ,0.7 ,0.5) * (1.0 - t) + Vec3::new(0.5, 0.7, 1.0) * t;
-            return Vec4::new(tempColSky.x, tempColSky.y, tempColSky.z, 0.);
+    // Radiance from infinitely far away along `ray`, weighed like `emission`.
+    pub fn escaped(&self, ray: &Ray, from: Option<Bounce>) -> Vec3 {
+        let dir = Vec3::unit_vec(ray.direction());
+        let mut radiance = self.environment.radiance(dir);
+        if let (Some(from), true) = (from, self.environment.is_sampled()) {
+            let pmf = self.light_sampler.pmf(from.point, from.normal, self.lights.len());
+            radiance = radiance * power_heuristic(from.pdf, pmf * self.environment.pdf(dir));
         }
-
-        fn getConeSample(dir: Vec3, coneAngle: f32) -> Vec3 {
-            let cosAngle = coneAngle.cos();
-
-            let z = (Vec3::random(0, 1).x).abs() * (1. - cosAngle) + cosAngle;
-            let phi = (Vec3::random(0, 1).x).abs() * 2. * PI;
-
-            let x = (1. - z * z).sqrt() * (phi).cos();
-            let y = (1. - z * z).sqrt() * (phi).sin();
-            let north = Vec3::new(0., 0., 1.);
-
-            let axis = Vec3::unit_vec(Vec3::cross(north, Vec3::unit_vec(dir)));
-            let angle = Vec3::dot(Vec3::unit_vec(dir), north).acos();
-
-            let r = angleAxis3x3(angle, axis);
-
-            let temp0 = Vec3::new(x, y, z);
-            let temp1 = Vec3::new(Vec3::dot(r[0], temp0) , Vec3::dot(r[1], temp0) , Vec3::dot(r[2], temp0));
-            return temp1;
+        for &i in &self.infinite {
+            let light = &self.lights[i];
+            let le = light.le(dir);
+            radiance = radiance + match from {
+                Some(from) => le * power_heuristic(from.pdf, self.light_sampler.pmf(from.point, from.normal, i) * light.pdf(from.point, dir)),
+                None => le,
+            };
         }
-    } 
+        radiance
+    }
 
-    pub fn color(world: &World, ray: &Ray) -> Vec3 {
-        let temp = World::hit(&world, &ray);
-        return Vec3::new(temp.x, temp.y, temp.z);
+    // False if anything is between `rec` and the light `sample` found.
+    fn unshadowed(&self, rec: &HitRecord, sample: &LightSample) -> bool {
+        let side = if Vec3::dot(rec.normal, sample.wi) > 0. { 1. } else { -1. };
+        let origin = rec.point + rec.normal * (0.00001 * side);
+        //stop just short of the light so its own surface doesn't count as a blocker
+        !self.occluded(&Ray::new(origin, sample.wi), 0.0001, sample.dist * 0.999)
     }
-}
\ No newline at end of file
+}